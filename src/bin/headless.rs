@@ -0,0 +1,230 @@
+use four_am::message::Message;
+use four_am::network::protocol::ServerMessage;
+use four_am::network::tcp::TcpServerTransport;
+use four_am::network::{local, ClientTransport, ServerTransport};
+use four_am::server::host::ServerHost;
+use four_am::server::replay::Replay;
+use four_am::server::save::SaveGame;
+use four_am::server::script;
+use four_am::server::server::{Server, ServerSettings};
+use std::time::{Duration, Instant};
+
+struct Options {
+    data_dir: String,
+    script: Option<String>,
+    listen: Option<String>,
+    load: Option<String>,
+    save: Option<String>,
+    record: Option<String>,
+    replay: Option<String>,
+    seed: Option<u64>,
+    ticks_per_second: u32,
+    max_ticks: Option<u64>,
+}
+
+const USAGE: &str =
+    "usage: headless [--data <dir>] [--script <file> | --listen <address>] [--tps <rate>] [--ticks <count>] [--load <file>] [--save <file>] [--seed <seed>] [--record <file>] [--replay <file>]";
+
+/// Reports a bad command line and exits, rather than panicking with a backtrace.
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", USAGE, message);
+    std::process::exit(2);
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: String) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("{} must be a number", flag)))
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        data_dir: "static/data".to_string(),
+        script: None,
+        listen: None,
+        load: None,
+        save: None,
+        record: None,
+        replay: None,
+        seed: None,
+        ticks_per_second: 0,
+        max_ticks: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage_error(&format!("{} needs a value", arg)))
+        };
+        match arg.as_str() {
+            "--data" => options.data_dir = value(),
+            "--script" => options.script = Some(value()),
+            "--listen" => options.listen = Some(value()),
+            "--load" => options.load = Some(value()),
+            "--save" => options.save = Some(value()),
+            "--record" => options.record = Some(value()),
+            "--replay" => options.replay = Some(value()),
+            "--seed" => options.seed = Some(number("--seed", value())),
+            "--tps" => options.ticks_per_second = number("--tps", value()),
+            "--ticks" => options.max_ticks = Some(number("--ticks", value())),
+            _ => usage_error(&format!("unknown argument {}", arg)),
+        }
+    }
+    options
+}
+
+fn print_message(tick: u64, message: ServerMessage) {
+    match message {
+        ServerMessage::Response { request, success } => {
+            println!("[{}] {:?} -> {}", tick, request, success)
+        }
+        ServerMessage::Event(Message::GameEvent(text, _, _)) => println!("[{}] {}", tick, text),
+        ServerMessage::Snapshot(_) | ServerMessage::Delta(_) => {}
+    }
+}
+
+/// Re-runs a recorded session and exits non-zero if the world ever differs from what
+/// was recorded.
+fn replay(options: &Options, path: &str) {
+    let raw = std::fs::read_to_string(path).expect("Couldn't read replay file");
+    let replay = Replay::from_json(&raw).expect("Invalid replay file");
+    let settings = ServerSettings {
+        seed: Some(replay.seed),
+        ..ServerSettings::default()
+    };
+    let mut server =
+        Server::load_from_path(&options.data_dir, settings).expect("Couldn't load server data");
+    match replay.verify(&mut server) {
+        Ok(ticks) => println!(
+            "Replay matched {} checkpoints over {} ticks",
+            replay.checkpoints.len(),
+            ticks
+        ),
+        Err(divergence) => {
+            println!(
+                "Replay diverged at tick {}: expected hash {:016x}, got {:016x}",
+                divergence.tick, divergence.expected, divergence.actual
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let options = parse_args();
+    if let Some(path) = &options.replay {
+        replay(&options, path);
+        return;
+    }
+    let commands = match &options.script {
+        Some(path) => {
+            let source = std::fs::read_to_string(path).expect("Couldn't read script file");
+            script::parse(&source).expect("Invalid script")
+        }
+        None => vec![],
+    };
+    if options.listen.is_some() && !commands.is_empty() {
+        usage_error("--script and --listen can't be combined");
+    }
+    if commands.is_empty() && options.listen.is_none() && options.max_ticks.is_none() {
+        usage_error("nothing to do without a script, a listen address or a tick count");
+    }
+
+    let settings = ServerSettings {
+        seed: options.seed,
+        ..ServerSettings::default()
+    };
+    let mut server =
+        Server::load_from_path(&options.data_dir, settings).expect("Couldn't load server data");
+    if let Some(path) = &options.load {
+        let raw = std::fs::read_to_string(path).expect("Couldn't read save file");
+        server.load(SaveGame::from_json(&raw).expect("Invalid save file"));
+    }
+    if options.record.is_some() {
+        if options.load.is_some() {
+            usage_error("--record can't be combined with --load");
+        }
+        server.start_recording();
+    }
+    // Scripts are fed through an in-process connection so they take the same path as
+    // requests from a real client.
+    let (mut script_client, transport): (
+        Option<Box<dyn ClientTransport>>,
+        Box<dyn ServerTransport>,
+    ) = match &options.listen {
+        Some(address) => (
+            None,
+            Box::new(TcpServerTransport::bind(address).expect("Couldn't bind listen address")),
+        ),
+        None => {
+            let (client, server) = local::pair();
+            (Some(Box::new(client)), Box::new(server))
+        }
+    };
+    let mut host = ServerHost::new(server, transport);
+    let tick_length = match options.ticks_per_second {
+        0 => None,
+        rate => Some(Duration::from_secs(1) / rate),
+    };
+
+    let expected_responses = commands.len();
+    if let Some(client) = &mut script_client {
+        for request in commands {
+            client.send(request);
+        }
+    }
+
+    let mut responses = 0;
+    let mut ticks = 0;
+    loop {
+        match options.max_ticks {
+            Some(max_ticks) if ticks >= max_ticks => break,
+            None if script_client.is_some() && responses >= expected_responses => break,
+            _ => {}
+        }
+
+        let started = Instant::now();
+        host.tick();
+        if let Some(client) = &mut script_client {
+            for message in client.receive() {
+                if let ServerMessage::Response { .. } = message {
+                    responses += 1;
+                }
+                print_message(ticks, message);
+            }
+        }
+        ticks += 1;
+
+        if let Some(tick_length) = tick_length {
+            let elapsed = started.elapsed();
+            if elapsed < tick_length {
+                std::thread::sleep(tick_length - elapsed);
+            }
+        }
+    }
+
+    if let Some(path) = &options.save {
+        let raw = host
+            .server_mut()
+            .save()
+            .to_json()
+            .expect("Couldn't serialize session");
+        std::fs::write(path, raw).expect("Couldn't write save file");
+    }
+
+    if let Some(path) = &options.record {
+        let raw = host
+            .server_mut()
+            .finish_recording()
+            .unwrap()
+            .to_json()
+            .expect("Couldn't serialize replay");
+        std::fs::write(path, raw).expect("Couldn't write replay file");
+    }
+
+    let position = host.server().get_player_position();
+    println!(
+        "Finished after {} ticks, player at ({}, {})",
+        ticks, position.x, position.y
+    );
+}
@@ -0,0 +1,291 @@
+use four_am::color::Color;
+use four_am::geom::Point;
+use four_am::map::Map;
+use four_am::server::map_builders::factories::{
+    bsp_builder, cave_builder, drunk_builder, random_builder, shop_builder, wfc_builder,
+};
+use four_am::server::map_builders::prefab::Prefabs;
+use four_am::server::map_builders::BuiltMap;
+use four_am::server::rng::RandomNumberGenerator;
+use four_am::tiles::TileDefinitions;
+use std::path::Path;
+
+struct Options {
+    data_dir: String,
+    builder: String,
+    seed: u64,
+    width: i32,
+    height: i32,
+    depth: i32,
+    scale: u32,
+    out: String,
+    history: bool,
+}
+
+const USAGE: &str =
+    "usage: mapgen <random|drunk|bsp|cave|wfc|shop> [--data <dir>] [--seed <seed>] [--size <width>x<height>] [--depth <depth>] [--scale <pixels>] [--out <prefix>] [--history]";
+
+/// Smallest width and height every builder can work with. Some loop forever or panic
+/// on anything smaller.
+const MIN_SIZE: i32 = 12;
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", USAGE, message);
+    std::process::exit(2);
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: String) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("{} must be a number", flag)))
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        data_dir: "static/data".to_string(),
+        builder: String::new(),
+        seed: 0,
+        width: 60,
+        height: 40,
+        depth: 1,
+        scale: 8,
+        out: "map".to_string(),
+        history: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage_error(&format!("{} needs a value", arg)))
+        };
+        match arg.as_str() {
+            "--data" => options.data_dir = value(),
+            "--seed" => options.seed = number("--seed", value()),
+            "--size" => {
+                let size = value();
+                let mut parts = size.split('x').map(|part| part.parse());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(width)), Some(Ok(height)), None) => {
+                        options.width = width;
+                        options.height = height;
+                    }
+                    _ => usage_error("--size must look like 60x40"),
+                }
+            }
+            "--depth" => options.depth = number("--depth", value()),
+            "--scale" => options.scale = number("--scale", value()),
+            "--out" => options.out = value(),
+            "--history" => options.history = true,
+            _ if options.builder.is_empty() && !arg.starts_with("--") => options.builder = arg,
+            _ => usage_error(&format!("unknown argument {}", arg)),
+        }
+    }
+    if options.builder.is_empty() {
+        usage_error("no builder given");
+    }
+    if options.width < MIN_SIZE || options.height < MIN_SIZE {
+        usage_error(&format!(
+            "--size must be at least {}x{}",
+            MIN_SIZE, MIN_SIZE
+        ));
+    }
+    if options.scale == 0 {
+        usage_error("--scale must be at least 1");
+    }
+    options
+}
+
+fn build(options: &Options, rng: &mut RandomNumberGenerator) -> BuiltMap {
+    let size = (options.width, options.height).into();
+    let (depth, history) = (options.depth, options.history);
+    match options.builder.as_str() {
+        "random" => random_builder(size, depth, history, rng),
+        "drunk" => drunk_builder(size, depth, history, rng),
+        "bsp" => bsp_builder(size, depth, history, rng),
+        "cave" => cave_builder(size, depth, history, rng),
+        "wfc" => wfc_builder(size, depth, history, rng),
+        "shop" => {
+            let prefabs = Prefabs::load_from_path(Path::new(&options.data_dir).join("prefabs.txt"))
+                .expect("Couldn't load prefabs");
+            shop_builder(size, &prefabs, history, rng)
+        }
+        builder => usage_error(&format!("unknown builder {}", builder)),
+    }
+}
+
+/// Draws the map with each tile's glyph, one line per row, with the starting position
+/// marked as `@`.
+fn render_text(map: &Map, tiles: &TileDefinitions, start: Option<Point>) -> String {
+    let mut text = String::with_capacity(map.tiles.len() + map.size.y as usize);
+    for (index, tile) in map.tiles.iter().enumerate() {
+        let point = map.index_to_point(index);
+        text.push(if Some(point) == start {
+            '@'
+        } else {
+            tiles.get(*tile).glyph.ch
+        });
+        if point.x == map.size.x - 1 {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// Draws each tile as a `scale` pixel square in its glyph's background colour, with the
+/// foreground colour filling the middle. The starting position is white.
+fn render_image(
+    map: &Map,
+    tiles: &TileDefinitions,
+    start: Option<Point>,
+    scale: u32,
+) -> image::RgbImage {
+    let to_rgb = |color: Color| {
+        image::Rgb([
+            (color.r * 255.0) as u8,
+            (color.g * 255.0) as u8,
+            (color.b * 255.0) as u8,
+        ])
+    };
+    let border = scale / 4;
+    let (width, height) = (map.size.x as u32, map.size.y as u32);
+    image::RgbImage::from_fn(width * scale, height * scale, |x, y| {
+        let point = Point::new((x / scale) as i32, (y / scale) as i32);
+        let (inner_x, inner_y) = (x % scale, y % scale);
+        let inside = (border..scale - border).contains(&inner_x)
+            && (border..scale - border).contains(&inner_y);
+        let glyph = tiles.get(map.get_type(point)).glyph;
+        if Some(point) == start {
+            image::Rgb([255, 255, 255])
+        } else if inside {
+            glyph.foreground.map_or(image::Rgb([0, 0, 0]), to_rgb)
+        } else {
+            glyph.background.map_or(image::Rgb([0, 0, 0]), to_rgb)
+        }
+    })
+}
+
+fn write_map(
+    options: &Options,
+    path: &str,
+    map: &Map,
+    tiles: &TileDefinitions,
+    start: Option<Point>,
+) {
+    std::fs::write(format!("{}.txt", path), render_text(map, tiles, start))
+        .expect("Couldn't write text map");
+    render_image(map, tiles, start, options.scale)
+        .save(format!("{}.png", path))
+        .expect("Couldn't write map image");
+}
+
+/// Writes the finished map to `<out>.txt` and `<out>.png`, and each step of building it
+/// to `<out>-000.txt`, `<out>-001.txt` and so on when there's a history.
+fn write_maps(options: &Options, built_map: &BuiltMap, tiles: &TileDefinitions) {
+    for (frame, map) in built_map.history.iter().enumerate() {
+        let path = format!("{}-{:03}", options.out, frame);
+        write_map(options, &path, map, tiles, None);
+    }
+    write_map(
+        options,
+        &options.out,
+        &built_map.map,
+        tiles,
+        built_map.starting_position,
+    );
+}
+
+fn main() {
+    let options = parse_args();
+    let tiles = TileDefinitions::load_from_path(Path::new(&options.data_dir).join("tiles.json"))
+        .expect("Couldn't load tile definitions");
+    let mut rng = RandomNumberGenerator::seeded(options.seed);
+    let built_map = build(&options, &mut rng);
+
+    write_maps(&options, &built_map, &tiles);
+    println!(
+        "Wrote {} ({}x{}, seed {}) to {}.txt and {}.png with {} history frames",
+        options.builder,
+        options.width,
+        options.height,
+        options.seed,
+        options.out,
+        options.out,
+        built_map.history.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, render_image, render_text, write_maps, Options};
+    use four_am::geom::Point;
+    use four_am::map::{Map, TileType};
+    use four_am::server::rng::RandomNumberGenerator;
+    use four_am::tiles::TileDefinitions;
+
+    fn tiles() -> TileDefinitions {
+        TileDefinitions::load_from_path("static/data/tiles.json").unwrap()
+    }
+
+    /// Wall all round a single floor tile, with the start just below it.
+    fn cell() -> Map {
+        let mut map = Map::new((3, 3), 1);
+        map.set_type(Point::new(1, 0), TileType::Floor);
+        map
+    }
+
+    #[test]
+    fn test_render_text() {
+        let text = render_text(&cell(), &tiles(), Some(Point::new(1, 1)));
+        assert_eq!(text, "#.#\n#@#\n###\n");
+    }
+
+    #[test]
+    fn test_render_image() {
+        let image = render_image(&cell(), &tiles(), Some(Point::new(1, 1)), 4);
+        assert_eq!(image.dimensions(), (12, 12));
+        // Walls are green on black, and the start is white all over.
+        assert_eq!(image.get_pixel(1, 1).0, [0, 255, 0]);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(image.get_pixel(4, 4).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(5, 5).0, [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_history_frames() {
+        let dir = std::env::temp_dir().join(format!("mapgen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("map").to_str().unwrap().to_string();
+        let options = Options {
+            data_dir: "static/data".to_string(),
+            builder: "bsp".to_string(),
+            seed: 7,
+            width: 30,
+            height: 20,
+            depth: 1,
+            scale: 2,
+            out: out.clone(),
+            history: true,
+        };
+        let built_map = build(&options, &mut RandomNumberGenerator::seeded(options.seed));
+        let frames = built_map.history.len();
+        assert!(frames > 1);
+        write_maps(&options, &built_map, &tiles());
+
+        for frame in 0..frames {
+            let text = std::fs::read_to_string(format!("{}-{:03}.txt", out, frame)).unwrap();
+            assert_eq!(text.lines().count(), 20);
+            assert!(!text.contains('@'));
+            let image = image::open(format!("{}-{:03}.png", out, frame)).unwrap();
+            assert_eq!(image.to_rgb().dimensions(), (60, 40));
+        }
+        assert!(!dir.join(format!("map-{:03}.txt", frames)).exists());
+        let text = std::fs::read_to_string(format!("{}.txt", out)).unwrap();
+        assert_eq!(
+            text,
+            render_text(&built_map.map, &tiles(), built_map.starting_position)
+        );
+        assert_eq!(text.matches('@').count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
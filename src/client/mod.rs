@@ -1 +1,2 @@
 pub mod network_client;
+pub mod replica;
@@ -1,9 +1,18 @@
-use crate::geom::Vector;
+use crate::client::replica::ReplicatedWorld;
+use crate::component;
+use crate::geom::{Point, Vector};
+use crate::network::local;
+use crate::network::protocol::{Request, ServerMessage};
+use crate::network::ClientTransport;
+use crate::server::host::ServerHost;
 use crate::server::server::Server;
 use legion::prelude::*;
+use std::path::PathBuf;
 
 pub struct NetworkClient {
-    server: Option<Server>,
+    transport: Box<dyn ClientTransport>,
+    local: Option<ServerHost>,
+    replica: ReplicatedWorld,
 }
 
 pub enum WorldType {
@@ -12,47 +21,127 @@ pub enum WorldType {
 }
 
 impl NetworkClient {
-    pub fn new() -> Self {
-        NetworkClient { server: None }
+    /// Runs `server` in-process, talking to it over a channel.
+    pub fn local(server: Server) -> Self {
+        let (client_transport, server_transport) = local::pair();
+        NetworkClient {
+            transport: Box::new(client_transport),
+            local: Some(ServerHost::new(server, Box::new(server_transport))),
+            replica: ReplicatedWorld::new(),
+        }
     }
 
-    pub fn unbind(&mut self) -> Server {
-        self.server.take().expect("Tried to unbind unbound server")
+    pub fn remote(transport: Box<dyn ClientTransport>) -> Self {
+        NetworkClient {
+            transport,
+            local: None,
+            replica: ReplicatedWorld::new(),
+        }
     }
 
-    pub fn bind(&mut self, server: Server) {
-        self.server = Some(server);
+    /// Records the in-process server's session to `path`. Remote servers record
+    /// themselves.
+    pub fn record_to(&mut self, path: impl Into<PathBuf>) {
+        if let Some(host) = &mut self.local {
+            host.record_to(path);
+        }
+    }
+
+    /// Advances the in-process server, if there is one. Remote servers tick on their own.
+    pub fn tick_server(&mut self) {
+        if let Some(host) = &mut self.local {
+            host.tick();
+        }
+    }
+
+    /// Applies any world updates from the server and hands back everything else.
+    pub fn poll(&mut self) -> Vec<ServerMessage> {
+        let mut messages = vec![];
+        for message in self.transport.receive() {
+            match message {
+                ServerMessage::Snapshot(snapshot) => self.replica.apply_snapshot(snapshot),
+                ServerMessage::Delta(delta) => self.replica.apply_delta(delta),
+                message => messages.push(message),
+            }
+        }
+        messages
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.replica.seed()
     }
 
     pub fn world(&self) -> &World {
-        &self.server.as_ref().unwrap().world
+        self.replica.world()
     }
 
     pub fn resources(&self) -> &Resources {
-        &self.server.as_ref().unwrap().resources
+        self.replica.resources()
     }
 
     pub fn get_player_inventory(&self) -> Vec<Entity> {
-        self.server.as_ref().unwrap().get_player_inventory()
+        let query = <Read<component::Inventory>>::query().filter(tag::<component::Player>());
+        query
+            .iter(self.world())
+            .next()
+            .map(|inventory| inventory.contents.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn entity_at(&self, point: Point) -> Option<Entity> {
+        let query = <Read<component::Position>>::query();
+        query
+            .iter_entities(self.world())
+            .find(|(_, position)| position.x == point.x && position.y == point.y)
+            .map(|(entity, _)| entity)
+    }
+
+    pub fn send(&mut self, request: Request) {
+        self.transport.send(request);
     }
 
-    pub fn try_move_player(&mut self, delta: impl Into<Vector>) -> bool {
+    pub fn try_move_player(&mut self, delta: impl Into<Vector>) {
         let delta = delta.into();
-        self.server
-            .as_mut()
-            .unwrap()
-            .try_move_player(delta.x, delta.y)
+        self.send(Request::Move {
+            dx: delta.x,
+            dy: delta.y,
+        });
+    }
+
+    pub fn try_interact(&mut self, delta: Vector) {
+        self.send(Request::Interact {
+            dx: delta.x,
+            dy: delta.y,
+        });
+    }
+
+    pub fn try_player_take(&mut self, delta: Vector) {
+        self.send(Request::Take {
+            dx: delta.x,
+            dy: delta.y,
+        });
     }
 
-    pub fn try_interact(&mut self, entity: Entity) -> bool {
-        self.server.as_mut().unwrap().try_interact(entity)
+    pub fn try_player_put(&mut self, delta: Vector, slot: usize) {
+        self.send(Request::Put {
+            dx: delta.x,
+            dy: delta.y,
+            slot,
+        });
     }
 
-    pub fn try_player_take(&mut self, entity: Entity) -> bool {
-        self.server.as_mut().unwrap().try_player_take(entity)
+    pub fn try_player_buy(&mut self, delta: Vector) {
+        self.send(Request::Buy {
+            dx: delta.x,
+            dy: delta.y,
+        });
     }
 
-    pub fn try_player_put(&mut self, entity: Entity, player_inv: Entity) -> bool {
-        self.server.as_mut().unwrap().try_player_put(entity, player_inv)
+    pub fn try_player_sell(&mut self, delta: Vector, slot: usize) {
+        self.send(Request::Sell {
+            dx: delta.x,
+            dy: delta.y,
+            slot,
+        });
     }
 }
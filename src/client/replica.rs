@@ -0,0 +1,148 @@
+use crate::component::{DisplayCabinet, Inventory, Player};
+use crate::map::Map;
+use crate::network::protocol::{EntityState, MapUpdate, NetworkId, WorldDelta, WorldSnapshot};
+use legion::prelude::*;
+use std::collections::HashMap;
+
+/// The client's own copy of the parts of the server world it's allowed to see, kept
+/// up to date from snapshots and deltas.
+pub struct ReplicatedWorld {
+    seed: Option<u64>,
+    world: World,
+    resources: Resources,
+    entities: HashMap<NetworkId, Entity>,
+}
+
+impl ReplicatedWorld {
+    pub fn new() -> Self {
+        let universe = Universe::new();
+        let world = universe.create_world();
+        let mut resources = Resources::default();
+        resources.insert(Map::default());
+        ReplicatedWorld {
+            seed: None,
+            world,
+            resources,
+            entities: HashMap::new(),
+        }
+    }
+
+    /// The server's seed, once the first snapshot has arrived.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    pub fn apply_snapshot(&mut self, snapshot: WorldSnapshot) {
+        self.seed = Some(snapshot.seed);
+        for (_, entity) in self.entities.drain() {
+            self.world.delete(entity);
+        }
+        self.apply(
+            snapshot.entities,
+            vec![],
+            Some(MapUpdate::Whole(snapshot.map)),
+        );
+    }
+
+    pub fn apply_delta(&mut self, delta: WorldDelta) {
+        self.apply(delta.changed, delta.removed, delta.map);
+    }
+
+    fn apply(
+        &mut self,
+        changed: Vec<EntityState>,
+        removed: Vec<NetworkId>,
+        map: Option<MapUpdate>,
+    ) {
+        match map {
+            Some(MapUpdate::Whole(map)) => *self.resources.get_mut::<Map>().unwrap() = map,
+            Some(MapUpdate::Tiles(tiles)) => {
+                let mut map = self.resources.get_mut::<Map>().unwrap();
+                for tile in tiles {
+                    tile.apply(&mut map);
+                }
+            }
+            None => {}
+        }
+
+        for id in removed {
+            if let Some(entity) = self.entities.remove(&id) {
+                self.world.delete(entity);
+            }
+        }
+
+        // Create everything up front so inventories can refer to items that arrived in
+        // the same batch.
+        for state in changed.iter() {
+            if !self.entities.contains_key(&state.id) {
+                let entity = self.world.insert((), vec![(state.name.clone(),)])[0];
+                self.entities.insert(state.id, entity);
+            }
+        }
+
+        let mut command_buffer = CommandBuffer::new(&self.world);
+        for state in changed {
+            let entity = self.entities[&state.id];
+            let inventory = state.inventory.map(|inventory| Inventory {
+                contents: inventory
+                    .contents
+                    .iter()
+                    .filter_map(|id| self.entities.get(id).cloned())
+                    .collect(),
+                capacity: inventory.capacity,
+            });
+            let world = &mut self.world;
+            sync_component(world, &mut command_buffer, entity, Some(state.name));
+            sync_component(world, &mut command_buffer, entity, state.position);
+            sync_component(world, &mut command_buffer, entity, state.renderable);
+            sync_component(world, &mut command_buffer, entity, inventory);
+            sync_component(world, &mut command_buffer, entity, state.value);
+            sync_component(world, &mut command_buffer, entity, state.wallet);
+            sync_tag(world, &mut command_buffer, entity, Player, state.player);
+            sync_tag(
+                world,
+                &mut command_buffer,
+                entity,
+                DisplayCabinet,
+                state.display_cabinet,
+            );
+        }
+        command_buffer.write(&mut self.world);
+    }
+}
+
+fn sync_component<T: Component>(
+    world: &mut World,
+    command_buffer: &mut CommandBuffer,
+    entity: Entity,
+    value: Option<T>,
+) {
+    match (world.get_component_mut::<T>(entity), value) {
+        (Some(mut current), Some(value)) => *current = value,
+        (Some(_), None) => command_buffer.remove_component::<T>(entity),
+        (None, Some(value)) => command_buffer.add_component(entity, value),
+        (None, None) => {}
+    }
+}
+
+fn sync_tag<T: Tag>(
+    world: &World,
+    command_buffer: &mut CommandBuffer,
+    entity: Entity,
+    tag: T,
+    present: bool,
+) {
+    match (world.get_tag::<T>(entity).is_some(), present) {
+        (false, true) => command_buffer.add_tag(entity, tag),
+        (true, false) => command_buffer.remove_tag::<T>(entity),
+        _ => {}
+    }
+}
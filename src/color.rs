@@ -1,4 +1,45 @@
-use quicksilver::graphics::Color;
+/// An RGBA colour with channels from 0 to 1. The server keeps its own colour type so it
+/// doesn't need the graphics library; the frontend converts when it draws.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const WHITE: Color = Color::rgb(1.0, 1.0, 1.0);
+    pub const BLACK: Color = Color::rgb(0.0, 0.0, 0.0);
+    pub const RED: Color = Color::rgb(1.0, 0.0, 0.0);
+    pub const GREEN: Color = Color::rgb(0.0, 1.0, 0.0);
+    pub const BLUE: Color = Color::rgb(0.0, 0.0, 1.0);
+    pub const YELLOW: Color = Color::rgb(1.0, 1.0, 0.0);
+
+    pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+        Color { r, g, b, a: 1.0 }
+    }
+
+    /// Parses `#rrggbb` or `#rgb`, panicking on anything else like the data loaders do.
+    pub fn from_hex(hex: &str) -> Self {
+        let hex = hex.trim_start_matches('#');
+        let hex = match hex.len() {
+            3 => hex.chars().flat_map(|ch| vec![ch, ch]).collect(),
+            6 => hex.to_string(),
+            _ => panic!("Malformed hex colour {}", hex),
+        };
+        let channel = |range: std::ops::Range<usize>| {
+            u8::from_str_radix(&hex[range], 16)
+                .unwrap_or_else(|_| panic!("Malformed hex colour {}", hex)) as f32
+                / 255.0
+        };
+        Color::rgb(channel(0..2), channel(2..4), channel(4..6))
+    }
+
+    pub fn with_alpha(self, a: f32) -> Self {
+        Color { a, ..self }
+    }
+}
 
 pub const BLACK: Color = Color::BLACK;
 pub const BLUE: Color = Color::BLUE;
@@ -21,3 +62,50 @@ pub const TAN: Color = Color {
     b: 80.0 / 255.0,
     a: 1.0,
 };
+
+// Serializes an optional colour as `[r, g, b, a]` so glyphs and log messages can cross the wire.
+pub mod option_color {
+    use super::Color;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        color: &Option<Color>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        color.map(|c| [c.r, c.g, c.b, c.a]).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Color>, D::Error> {
+        let channels = Option::<[f32; 4]>::deserialize(deserializer)?;
+        Ok(channels.map(|[r, g, b, a]| Color { r, g, b, a }))
+    }
+}
+
+// Same as `option_color`, for colours that are always there.
+pub mod rgba {
+    use super::Color;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        [color.r, color.g, color.b, color.a].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        let [r, g, b, a] = <[f32; 4]>::deserialize(deserializer)?;
+        Ok(Color { r, g, b, a })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Color;
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(Color::from_hex("#ff0000"), Color::RED);
+        assert_eq!(Color::from_hex("#0f0"), Color::GREEN);
+        assert_eq!(Color::from_hex("808080").r, 128.0 / 255.0);
+    }
+}
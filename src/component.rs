@@ -1,8 +1,10 @@
+use crate::color::Color;
 use crate::frontend::glyph::Glyph;
 use crate::geom::Point;
 use legion::prelude::Entity;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -14,7 +16,7 @@ impl Into<Point> for Position {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Renderable {
     pub glyph: Glyph,
 }
@@ -23,33 +25,113 @@ pub struct Renderable {
 pub struct Player;
 
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Name {
     pub name: String,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TurnState {
     PENDING,
     ACTIVE,
     DONE,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ActiveTurn {
     pub state: TurnState,
+    /// Energy spent by the action that ended the turn.
+    pub cost: i32,
+}
+
+impl ActiveTurn {
+    pub fn pending() -> Self {
+        ActiveTurn {
+            state: TurnState::PENDING,
+            cost: 0,
+        }
+    }
+
+    pub fn finish(&mut self, cost: i32) {
+        self.state = TurnState::DONE;
+        self.cost = cost;
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TileBlocker;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Priority {
     pub value: u8,
 }
+
+/// Energy gained every time step. Entities act once they've saved up a turn's worth.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Speed {
+    pub value: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Energy {
+    pub value: i32,
+}
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct DisplayCabinet;
 
+/// What an entity can see from where it stands. `dirty` is set whenever it moves so the
+/// viewshed system knows to work `visible_tiles` out again.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Viewshed {
+    pub range: i32,
+    pub visible_tiles: Vec<Point>,
+    pub dirty: bool,
+}
+
+/// What an item sells for.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Value {
+    pub price: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wallet {
+    pub coins: u32,
+}
+
+/// Lights up the tiles around an entity, fading out towards `radius`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LightSource {
+    pub radius: i32,
+    #[serde(with = "crate::color::rgba")]
+    pub color: Color,
+    pub intensity: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum CustomerState {
+    /// Heading for a display cabinet, picking one first if there's no target yet.
+    Browsing {
+        target: Option<Point>,
+    },
+    Leaving,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Customer {
+    pub state: CustomerState,
+    /// Cabinets left to look at before heading out.
+    pub visits_left: u8,
+}
+
+/// Lets customers in through the level entrance whenever it takes a turn.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CustomerSpawner {
+    /// Entity factory id of the customers to spawn.
+    pub customer: String,
+    pub max_customers: u8,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Inventory {
     pub contents: Vec<Entity>,
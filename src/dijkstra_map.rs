@@ -0,0 +1,194 @@
+use crate::geom::Point;
+use crate::map::{Map, TilePos};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Walking distance from every tile of a map to the nearest of a set of goals. Goals can
+/// start out with a weight of their own, so a close but unappealing goal can lose out to
+/// a better one further away. Walls and anything else `blocked` on the map can't be
+/// walked through.
+// Based on http://www.roguebasin.com/index.php?title=The_Incredible_Power_of_Dijkstra_Maps
+#[derive(Clone, Debug)]
+pub struct DijkstraMap {
+    width: i32,
+    distances: Vec<Option<i32>>,
+    goals: Vec<(Point, i32)>,
+    max_distance: i32,
+    /// The map's `blocked` tiles as of the last time distances were worked out.
+    blocked: Vec<bool>,
+}
+
+impl DijkstraMap {
+    /// Works out distances to `goals`, giving up on tiles more than `max_distance` away.
+    pub fn new(map: &Map, goals: &[(Point, i32)], max_distance: i32) -> Self {
+        let mut dijkstra_map = DijkstraMap {
+            width: map.size.x,
+            distances: vec![],
+            goals: goals.to_vec(),
+            max_distance,
+            blocked: vec![],
+        };
+        dijkstra_map.build(map);
+        dijkstra_map
+    }
+
+    pub fn set_goals(&mut self, map: &Map, goals: &[(Point, i32)]) {
+        self.goals = goals.to_vec();
+        self.build(map);
+    }
+
+    /// Works distances out again if anything on `map` has been blocked or unblocked since
+    /// they were last calculated. Returns whether they were.
+    pub fn refresh(&mut self, map: &Map) -> bool {
+        if self.blocked == map.blocked {
+            return false;
+        }
+        self.build(map);
+        true
+    }
+
+    fn build(&mut self, map: &Map) {
+        self.width = map.size.x;
+        self.blocked = map.blocked.clone();
+        self.distances = vec![None; map.tiles.len()];
+
+        let mut queue = BinaryHeap::new();
+        for (goal, weight) in self.goals.iter() {
+            if map.in_bounds(*goal) {
+                queue.push(Reverse((*weight, goal.x, goal.y)));
+            }
+        }
+        while let Some(Reverse((distance, x, y))) = queue.pop() {
+            let index = map.coord_to_index(x, y);
+            if self.distances[index].is_some() {
+                continue;
+            }
+            self.distances[index] = Some(distance);
+            for (next, cost) in TilePos(x, y, distance).successors(map, self.max_distance) {
+                if self.distances[map.coord_to_index(next.0, next.1)].is_none() {
+                    queue.push(Reverse((distance + cost, next.0, next.1)));
+                }
+            }
+        }
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let height = self.distances.len() as i32 / self.width.max(1);
+        if point.x < 0 || point.y < 0 || point.x >= self.width || point.y >= height {
+            return None;
+        }
+        Some((point.y * self.width + point.x) as usize)
+    }
+
+    /// How far `point` is from the nearest goal, or `None` if no goal can be reached.
+    pub fn distance(&self, point: Point) -> Option<i32> {
+        self.index(point).and_then(|index| self.distances[index])
+    }
+
+    pub fn is_reachable(&self, point: Point) -> bool {
+        self.distance(point).is_some()
+    }
+
+    /// Every tile a goal can be reached from.
+    pub fn reachable(&self) -> impl Iterator<Item = Point> + '_ {
+        let width = self.width;
+        self.distances
+            .iter()
+            .enumerate()
+            .filter(|(_, distance)| distance.is_some())
+            .map(move |(index, _)| Point::new(index as i32 % width, index as i32 / width))
+    }
+
+    /// The reachable tile furthest from every goal, favouring the first one found when
+    /// there's a tie.
+    pub fn farthest(&self) -> Option<Point> {
+        let mut farthest: Option<(Point, i32)> = None;
+        for point in self.reachable() {
+            let distance = self.distance(point).unwrap();
+            if farthest.map_or(true, |(_, best)| distance > best) {
+                farthest = Some((point, distance));
+            }
+        }
+        farthest.map(|(point, _)| point)
+    }
+
+    /// The neighbouring tile of `from` that is closest to a goal, if any gets closer.
+    pub fn step_towards(&self, map: &Map, from: Point) -> Option<Point> {
+        let current = self.distance(from)?;
+        TilePos(from.x, from.y, 0)
+            .successors(map, 1)
+            .into_iter()
+            .map(|(next, _)| Point::new(next.0, next.1))
+            .filter_map(|next| self.distance(next).map(|distance| (next, distance)))
+            .filter(|(_, distance)| *distance < current)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(next, _)| next)
+    }
+
+    /// A map for running away from this one's goals. Rather than just heading for the
+    /// highest number, fleeing this way prefers open ground over dead ends.
+    pub fn fleeing(&self, map: &Map) -> DijkstraMap {
+        let goals = self
+            .reachable()
+            .map(|point| (point, self.distance(point).unwrap() * -6 / 5))
+            .collect::<Vec<_>>();
+        DijkstraMap::new(map, &goals, self.max_distance)
+    }
+
+    /// The neighbouring tile of `from` that best gets away from every goal.
+    pub fn step_away(&self, map: &Map, from: Point) -> Option<Point> {
+        self.fleeing(map).step_towards(map, from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DijkstraMap;
+    use crate::geom::Point;
+    use crate::map::{Map, TileType};
+    use crate::tiles::TileDefinitions;
+
+    fn tiles() -> TileDefinitions {
+        TileDefinitions::load_from_path("static/data/tiles.json").unwrap()
+    }
+
+    /// A corridor along the middle of the map, with the map's edges left as walls.
+    fn corridor(length: i32) -> Map {
+        let mut map = Map::new((length + 2, 3), 0);
+        for x in 1..=length {
+            map.set_type(Point::new(x, 1), TileType::Floor);
+        }
+        map.refresh_blocked(&tiles());
+        map
+    }
+
+    #[test]
+    fn test_distances_from_nearest_weighted_goal() {
+        let map = corridor(9);
+        let goals = [(Point::new(1, 1), 0), (Point::new(9, 1), 3)];
+        let dijkstra_map = DijkstraMap::new(&map, &goals, 100);
+        assert_eq!(dijkstra_map.distance(Point::new(4, 1)), Some(3));
+        assert_eq!(dijkstra_map.distance(Point::new(7, 1)), Some(5));
+        assert_eq!(dijkstra_map.distance(Point::new(0, 0)), None);
+        assert_eq!(dijkstra_map.farthest(), Some(Point::new(6, 1)));
+    }
+
+    #[test]
+    fn test_steps_and_refresh() {
+        let mut map = corridor(9);
+        let mut dijkstra_map = DijkstraMap::new(&map, &[(Point::new(1, 1), 0)], 100);
+        let from = Point::new(5, 1);
+        assert_eq!(
+            dijkstra_map.step_towards(&map, from),
+            Some(Point::new(4, 1))
+        );
+        assert_eq!(dijkstra_map.step_away(&map, from), Some(Point::new(6, 1)));
+        assert!(!dijkstra_map.refresh(&map));
+
+        map.set_type(Point::new(3, 1), TileType::Wall);
+        map.refresh_blocked(&tiles());
+        assert!(dijkstra_map.refresh(&map));
+        assert!(!dijkstra_map.is_reachable(from));
+        assert_eq!(dijkstra_map.step_towards(&map, from), None);
+    }
+}
@@ -6,6 +6,10 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     Io(std::io::Error),
     Font(RTError),
+    Script(String),
+    Save(String),
+    Replay(String),
+    Prefab(String),
 }
 
 impl From<RTError> for Error {
@@ -2,13 +2,47 @@ use crate::component;
 use crate::frontend::glyph::Glyph;
 
 use super::screen::terminal::Terminal;
+use crate::color::Color;
 use crate::{
     client::network_client::NetworkClient,
     geom::{Point, Vector},
-    map::{Map, TileType},
+    map::Map,
+    tiles::TileDefinitions,
 };
 use legion::prelude::*;
-use quicksilver::graphics::Color;
+
+/// Light every visible tile gets even with no light source nearby, so the shop is dim
+/// rather than pitch black.
+const AMBIENT_LIGHT: f32 = 0.3;
+
+/// Tints a glyph by the light falling on its tile.
+fn lit(glyph: &Glyph, light: [f32; 3]) -> Glyph {
+    let tint = |color: Color| Color {
+        r: color.r * (AMBIENT_LIGHT + light[0]).min(1.0),
+        g: color.g * (AMBIENT_LIGHT + light[1]).min(1.0),
+        b: color.b * (AMBIENT_LIGHT + light[2]).min(1.0),
+        a: color.a,
+    };
+    Glyph {
+        foreground: glyph.foreground.map(tint),
+        background: glyph.background.map(tint),
+        ..*glyph
+    }
+}
+
+/// How the player sees the tile at `index`: lit when it's in sight, greyed out when
+/// it's only remembered from earlier, and not at all when it's never been seen.
+fn tile_glyph(map: &Map, tiles: &TileDefinitions, index: usize) -> Option<Glyph> {
+    if !map.revealed_tiles[index] {
+        return None;
+    }
+    let glyph = tiles.get(map.tiles[index]).glyph;
+    if map.visible_tiles[index] {
+        Some(lit(&glyph, map.light[index]))
+    } else {
+        Some(glyph.greyscale())
+    }
+}
 
 pub struct Camera {
     dimensions: Vector,
@@ -70,7 +104,18 @@ impl Camera {
         (min_x, max_x, min_y, max_y)
     }
 
-    pub fn render(&self, client: &NetworkClient, terminal: &mut Terminal) {
+    /// Marks out a path on the top layer of the map.
+    pub fn render_path(&self, path: impl Iterator<Item = Point>, terminal: &mut Terminal) {
+        let glyph = Glyph::from('*', Some(Color::YELLOW.with_alpha(0.6)), None);
+        for point in path {
+            let (x, y) = self.project(point).to_tuple();
+            if x >= 0 && y >= 0 && x < self.dimensions.x && y < self.dimensions.y {
+                terminal.draw_layer((x, y), &glyph, 2);
+            }
+        }
+    }
+
+    pub fn render(&self, client: &NetworkClient, tiles: &TileDefinitions, terminal: &mut Terminal) {
         let (min_x, max_x, min_y, max_y) = self.get_screen_bounds();
         let (map_width, map_height) = client.resources().get::<Map>().unwrap().size.to_tuple();
 
@@ -80,21 +125,10 @@ impl Camera {
                 let y = y as i32;
                 if tx >= 0 && tx < map_width && ty >= 0 && ty < map_height {
                     let map = client.resources().get::<Map>().unwrap();
-                    let tile = map
-                        .tiles
-                        .get((tx + ty * map_width) as usize)
-                        .expect(&format!("Couldn't find {} {}", tx, ty));
-
-                    let glyph = match tile {
-                        TileType::Wall => Glyph::from('#', Some(Color::GREEN), None),
-                        TileType::Floor => {
-                            Glyph::from('.', Some(Color::from_rgba(128, 128, 128, 1.0)), None)
-                        }
-                        TileType::Digging => {
-                            Glyph::from('>', Some(Color::from_rgba(128, 20, 20, 1.0)), None)
-                        }
-                    };
-                    terminal.draw((x, y), &glyph);
+                    let index = (tx + ty * map_width) as usize;
+                    if let Some(glyph) = tile_glyph(&map, tiles, index) {
+                        terminal.draw((x, y), &glyph);
+                    }
                 } else {
                     let glyph = Glyph::from('-', Some(Color::WHITE), None);
                     terminal.draw((x, y), &glyph);
@@ -102,21 +136,34 @@ impl Camera {
             }
         }
 
+        let map = client.resources().get::<Map>().unwrap();
+        let is_visible = |pos: &component::Position| {
+            let point = (pos.x, pos.y).into();
+            map.in_bounds(point) && map.visible_tiles[map.point_to_index(point)]
+        };
+        let light_at = |pos: &component::Position| map.light[map.coord_to_index(pos.x, pos.y)];
+
         let query = <(Read<component::Position>, Read<component::Renderable>)>::query();
         let world = client.world();
-        let mut data = query.iter(world).collect::<Vec<_>>();
+        let mut data = query
+            .iter(world)
+            .filter(|(pos, _)| is_visible(pos))
+            .collect::<Vec<_>>();
         data.sort_by(|a, b| b.1.glyph.render_order.cmp(&a.1.glyph.render_order));
         for (pos, render) in data.iter() {
             let (x, y) = self.project((pos.x, pos.y).into()).to_tuple();
             if x >= 0 && y >= 0 && x < (self.dimensions.x) && y < (self.dimensions.y) {
-                terminal.draw((x, y), &render.glyph);
+                terminal.draw((x, y), &lit(&render.glyph, light_at(pos)));
             }
         }
 
         let query = <(Read<component::Position>, Read<component::Inventory>)>::query().filter(tag::<component::DisplayCabinet>());
 
         let world = client.world();
-        let data = query.iter(world).collect::<Vec<_>>();
+        let data = query
+            .iter(world)
+            .filter(|(pos, _)| is_visible(pos))
+            .collect::<Vec<_>>();
         for (pos, inv) in data.iter() {
             let (x, y) = self.project((pos.x, pos.y).into()).to_tuple();
             if x >= 0 && y >= 0 && x < (self.dimensions.x) && y < (self.dimensions.y) {
@@ -124,10 +171,43 @@ impl Camera {
                 if !contents.is_empty()  {
                     let renderable = world.get_component::<component::Renderable>(*contents.first().unwrap());
                     if let Some(renderable) = renderable {
-                        terminal.draw_layer((x, y), &renderable.glyph, 1);
+                        terminal.draw_layer((x, y), &lit(&renderable.glyph, light_at(pos)), 1);
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{lit, tile_glyph};
+    use crate::geom::Point;
+    use crate::map::{Map, TileType};
+    use crate::tiles::TileDefinitions;
+
+    #[test]
+    fn test_fog_of_war() {
+        let tiles = TileDefinitions::load_from_path("static/data/tiles.json").unwrap();
+        let mut map = Map::new((3, 1), 0);
+        for x in 0..3 {
+            map.set_type(Point::new(x, 0), TileType::Floor);
+        }
+        let (unseen, remembered, visible) = (0, 1, 2);
+        map.set_revealed(Point::new(1, 0));
+        map.set_revealed(Point::new(2, 0));
+        map.set_visible(Point::new(2, 0));
+        map.light[visible] = [0.5, 0.2, 0.0];
+
+        let floor = tiles.get(TileType::Floor).glyph;
+        assert_eq!(tile_glyph(&map, &tiles, unseen), None);
+        assert_eq!(
+            tile_glyph(&map, &tiles, remembered),
+            Some(floor.greyscale())
+        );
+        assert_eq!(
+            tile_glyph(&map, &tiles, visible),
+            Some(lit(&floor, [0.5, 0.2, 0.0]))
+        );
+    }
+}
@@ -7,15 +7,121 @@ use crate::frontend::ui::{draw_ui, UIElement};
 use crate::geom::{Point, Vector};
 
 use crate::client::network_client::NetworkClient;
+use crate::color::Color;
 use crate::component;
+use crate::map::Map;
 use crate::message::Message;
-use crate::{map::Map, resources::log::GameLog};
+use crate::network::protocol::{Request, ServerMessage};
+use crate::resources::log::GameLog;
+use crate::tiles::TileDefinitions;
 
-use quicksilver::graphics::{Color, Graphics};
+use quicksilver::graphics::Graphics;
 use quicksilver::lifecycle::{Event, EventStream, Key, Window};
 
 use super::{screen::terminal::Terminal, ui::{InventoryEntry, DisplayCaseWidget}};
 use legion::prelude::*;
+use pathfinding::prelude::astar;
+use std::collections::{HashSet, VecDeque};
+
+/// Longest walk a click can send the player on.
+const MAX_TRAVEL_DISTANCE: i32 = 100;
+
+/// A walk to a clicked tile, taken one move per turn.
+struct Travel {
+    path: VecDeque<Point>,
+    /// Whether the last step is still waiting on a response.
+    waiting: bool,
+    /// Everything in view when the walk started.
+    seen: HashSet<Entity>,
+}
+
+/// What a walk in progress does next.
+#[derive(Debug, PartialEq)]
+enum TravelStep {
+    /// The last step hasn't been answered yet.
+    Wait,
+    Move(Vector),
+    /// Something new came into view.
+    Spotted,
+    Arrived,
+}
+
+impl Travel {
+    fn new(path: Vec<Point>, seen: HashSet<Entity>) -> Self {
+        Travel {
+            path: path.into_iter().collect(),
+            waiting: false,
+            seen,
+        }
+    }
+
+    /// The next step from `from`, given everything the player can see right now.
+    fn next_step(&self, from: Point, visible: &HashSet<Entity>) -> TravelStep {
+        if self.waiting {
+            return TravelStep::Wait;
+        }
+        if visible.iter().any(|entity| !self.seen.contains(entity)) {
+            return TravelStep::Spotted;
+        }
+        match self.path.front() {
+            Some(next) => TravelStep::Move(*next - from),
+            None => TravelStep::Arrived,
+        }
+    }
+
+    /// Keeps track of the walk as messages come in from the server, saying whether it
+    /// should go on. Anything worth a log message is worth stopping for, as is a step
+    /// that didn't work out.
+    fn handle(&mut self, message: &ServerMessage) -> bool {
+        match message {
+            ServerMessage::Event(Message::GameEvent(..)) => false,
+            ServerMessage::Response {
+                request: Request::Move { .. },
+                success,
+            } => {
+                if *success {
+                    self.path.pop_front();
+                    self.waiting = false;
+                }
+                *success
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Shortest walk from `from` to `to` over tiles the player has seen, not counting
+/// `from` itself. Tiles in `occupied` are walked around.
+fn plan_path(map: &Map, from: Point, to: Point, occupied: &HashSet<Point>) -> Option<Vec<Point>> {
+    let neighbours = [
+        Vector::new(0, 1),
+        Vector::new(0, -1),
+        Vector::new(1, 0),
+        Vector::new(-1, 0),
+    ];
+    let (path, cost) = astar(
+        &from,
+        |point| {
+            neighbours
+                .iter()
+                .map(|offset| *point + *offset)
+                .filter(|next| {
+                    map.in_bounds(*next)
+                        && !map.is_blocked(*next)
+                        && map.revealed_tiles[map.point_to_index(*next)]
+                        && !occupied.contains(next)
+                })
+                .map(|next| (next, 1))
+                .collect::<Vec<_>>()
+        },
+        |point| (point.x - to.x).abs() + (point.y - to.y).abs(),
+        |point| *point == to,
+    )?;
+    if cost > MAX_TRAVEL_DISTANCE {
+        return None;
+    }
+    Some(path.into_iter().skip(1).collect())
+}
 
 pub struct RenderContext {
     tile_ctx: TileContext,
@@ -79,10 +185,17 @@ pub struct Client {
     layout: LayoutManager,
     camera: Camera,
     mode: UIMode,
+    travel: Option<Travel>,
+    tiles: TileDefinitions,
 }
 
 impl Client {
-    pub async fn new(window: Window, gfx: Graphics, events: EventStream) -> Self {
+    pub async fn new(
+        window: Window,
+        gfx: Graphics,
+        events: EventStream,
+        network_client: NetworkClient,
+    ) -> Self {
         let x = 60;
         let y = 40;
         let tileset = tileset::Tileset::from_font(&gfx, "Px437_Wyse700b-2y.ttf", 16.0 / 8.0)
@@ -120,17 +233,29 @@ impl Client {
                 mouse_position: (0, 0).into(),
             },
             camera: Camera::new((50, 46), (x / 2, y / 2)),
-            network_client: NetworkClient::new(),
+            network_client,
             layout,
-            mode: UIMode::None
+            mode: UIMode::None,
+            travel: None,
+            tiles: TileDefinitions::load().await,
         }
     }
 
-    pub fn sync(&mut self) {
+    /// Points the camera at the player, returning false if there isn't one yet. Until then
+    /// it looks at the middle of the map, which is where it's being built.
+    pub fn sync(&mut self) -> bool {
         let query = <Read<component::Position>>::query().filter(tag::<component::Player>());
+        let mut found = false;
         for position in query.iter(self.network_client.world()) {
             self.camera.set_focus(*position);
+            found = true;
+        }
+        if !found {
+            if let Some(map) = self.network_client.resources().get::<Map>() {
+                self.camera.set_focus((map.size.x / 2, map.size.y / 2));
+            }
         }
+        found
     }
 
     pub async fn tick(&mut self) {
@@ -139,11 +264,130 @@ impl Client {
         }
     }
 
-    pub fn process_messages(&mut self, messages: Vec<Message>) {
-        for message in messages {
+    pub fn process_messages(&mut self) {
+        for message in self.network_client.poll() {
+            let going_on = self.travel.as_mut().map_or(true, |travel| travel.handle(&message));
+            if !going_on {
+                self.stop_travel();
+            }
             match message {
-                Message::GameEvent(msg, fg, bg) => self.log.push(msg.as_str(), fg, bg),
+                ServerMessage::Event(Message::GameEvent(msg, fg, bg)) => {
+                    self.log.push(msg.as_str(), fg, bg);
+                }
+                ServerMessage::Response {
+                    request: Request::Move { dx, dy },
+                    success: true,
+                } => {
+                    self.camera.move_focus((dx, dy));
+                }
+                ServerMessage::Response {
+                    request: Request::Descend,
+                    success: true,
+                }
+                | ServerMessage::Response {
+                    request: Request::Ascend,
+                    success: true,
+                } => {
+                    self.sync();
+                }
+                // A sale that goes through is reported by the server itself.
+                ServerMessage::Response {
+                    request: Request::Buy { .. },
+                    success: false,
+                }
+                | ServerMessage::Response {
+                    request: Request::Sell { .. },
+                    success: false,
+                } => {
+                    self.log.push("The sale didn't go through.", Some(Color::RED), None);
+                }
+                ServerMessage::Response { .. } => {}
+            }
+        }
+        self.continue_travel();
+    }
+
+    fn player_position(&self) -> Option<Point> {
+        let query = <Read<component::Position>>::query().filter(tag::<component::Player>());
+        query.iter(self.network_client.world()).next().map(|position| (*position).into())
+    }
+
+    /// Everything with a position the player can currently see, other than the player.
+    fn visible_entities(&self) -> HashSet<Entity> {
+        let map = self.resources().get::<Map>().unwrap();
+        let world = self.network_client.world();
+        let query = <Read<component::Position>>::query();
+        query
+            .iter_entities(world)
+            .filter(|(entity, _)| world.get_tag::<component::Player>(*entity).is_none())
+            .filter(|(_, position)| {
+                let point = (position.x, position.y).into();
+                map.in_bounds(point) && map.visible_tiles[map.point_to_index(point)]
+            })
+            .map(|(entity, _)| entity)
+            .collect()
+    }
+
+    /// Starts walking the player to `destination`, if there's a known way there.
+    fn travel_to(&mut self, destination: Point) {
+        let from = match self.player_position() {
+            Some(from) => from,
+            None => return,
+        };
+        let seen = self.visible_entities();
+        let world = self.network_client.world();
+        let occupied = seen
+            .iter()
+            .filter_map(|entity| world.get_component::<component::Position>(*entity))
+            .map(|position| (*position).into())
+            .collect::<HashSet<Point>>();
+        let path = {
+            let map = self.resources().get::<Map>().unwrap();
+            if from == destination || !map.in_bounds(destination) {
+                return;
+            }
+            if map.is_blocked(destination) {
+                // There's no walking onto it, so say what it is instead.
+                if map.revealed_tiles[map.point_to_index(destination)] {
+                    let description = self.tiles.get(map.get_type(destination)).description.clone();
+                    std::mem::drop(map);
+                    self.log.push(&description, None, None);
+                }
+                return;
             }
+            plan_path(&map, from, destination, &occupied)
+        };
+        match path {
+            Some(path) => {
+                self.travel = Some(Travel::new(path, seen))
+            }
+            None => self.log.push("You don't know a way there.", Some(Color::RED), None),
+        }
+    }
+
+    fn stop_travel(&mut self) {
+        self.travel = None;
+    }
+
+    /// Takes the next step of the current walk once the last one has been carried out,
+    /// unless something new has come into view.
+    fn continue_travel(&mut self) {
+        let step = match (&self.travel, self.player_position()) {
+            (Some(travel), Some(from)) => travel.next_step(from, &self.visible_entities()),
+            (Some(_), None) => TravelStep::Arrived,
+            (None, _) => return,
+        };
+        match step {
+            TravelStep::Wait => {}
+            TravelStep::Move(delta) => {
+                self.network_client.try_move_player(delta);
+                self.travel.as_mut().unwrap().waiting = true;
+            }
+            TravelStep::Spotted => {
+                self.log.push("You spot something and stop.", Some(Color::YELLOW), None);
+                self.stop_travel();
+            }
+            TravelStep::Arrived => self.stop_travel(),
         }
     }
 
@@ -189,6 +433,8 @@ impl Client {
 
     pub fn handle_key(&mut self, key: Key, is_down: bool) {
         if is_down {
+            // Any key takes control back from a walk in progress.
+            self.stop_travel();
             match &mut self.mode {
                 UIMode::None => {
                     match key {
@@ -200,9 +446,15 @@ impl Client {
                         Key::Left => self.handle_focus((-1, 0)),
                         Key::Down => self.handle_focus((0, 1)),
                         Key::Right => self.handle_focus((1, 0)),
-                        Key::C => self.sync(),
+                        Key::C => {
+                            self.sync();
+                        }
                         Key::E => self.mode = UIMode::Interact,
                         Key::Space => self.handle_move((0, 0)),
+                        Key::Period => self.network_client.send(Request::Descend),
+                        Key::Comma => self.network_client.send(Request::Ascend),
+                        Key::F5 => self.network_client.send(Request::Save),
+                        Key::F9 => self.network_client.send(Request::Load),
                         Key::Escape => panic!("DIE DIE DIE"),
                         _ => {}
                     }
@@ -236,39 +488,39 @@ impl Client {
         }
     }
 
+    fn inventory_entry(&self, item: Entity) -> InventoryEntry {
+        let world = self.network_client.world();
+        let name = world.get_component::<component::Name>(item).unwrap().name.clone();
+        match world.get_component::<component::Value>(item) {
+            Some(value) => InventoryEntry::new(item, format!("{} ({}c)", name, value.price)),
+            None => InventoryEntry::new(item, name),
+        }
+    }
+
     pub fn handle_interact(&mut self, delta: impl Into<Vector>, take: bool) {
         self.mode = UIMode::None;
         let delta = delta.into();
         let query = <Read<component::Position>>::query().filter(tag::<component::Player>());
         let player: Entity = query.iter_entities(&self.network_client.world()).take(1).next().expect("Couldn't find player").0;
         let position = *self.network_client.world().get_component::<component::Position>(player).expect("Player didn't have a position.");
-        let map = self.network_client.resources().get::<Map>().unwrap();
-        let position: Vector = (position.x, position.y).into();
-        let index = map.point_to_index((position + delta.into()).to_tuple().into());
-        let mut found_entity = None;
-        if let Some(entity) = map.tile_content.get(index) {
-            if let Some(entity) = entity {
-                let name = self.network_client.world().get_component::<component::Name>(*entity).expect("This entity didn't have a name");
-                self.log.push(
-                    &format!("You interacted with {}", name.name),
-                    Some(Color::GREEN),
-                    None,
-                );
-                found_entity = Some(*entity);
-            }
+        let position: Point = position.into();
+        let found_entity = self.network_client.entity_at(position + delta);
+        if let Some(entity) = found_entity {
+            let name = self.network_client.world().get_component::<component::Name>(entity).expect("This entity didn't have a name");
+            self.log.push(
+                &format!("You interacted with {}", name.name),
+                Some(Color::GREEN),
+                None,
+            );
         }
-        std::mem::drop(map);
         if let Some(entity) = found_entity {
             let inv = self.network_client.world().get_component::<component::Inventory>(entity);
             if let Some(inv) = inv {
-                let contents = inv.contents.iter().map(|i|
-                    InventoryEntry::new(*i, self.network_client.world().get_component::<component::Name>(*i).unwrap().name.clone())
-                ).collect();
+                let contents = inv.contents.iter().map(|i| self.inventory_entry(*i)).collect();
                 let player_inv = self.network_client.get_player_inventory();
-                let player_inv = player_inv.iter().map(|i|
-                    InventoryEntry::new(*i, self.network_client.world().get_component::<component::Name>(*i).unwrap().name.clone())
-                ).collect();
-                self.mode = UIMode::Overlay(Box::new(DisplayCaseWidget::new(entity, contents, player_inv)));
+                let player_inv = player_inv.iter().map(|i| self.inventory_entry(*i)).collect();
+                let cabinet = self.network_client.world().get_tag::<component::DisplayCabinet>(entity).is_some();
+                self.mode = UIMode::Overlay(Box::new(DisplayCaseWidget::new(delta, contents, player_inv, cabinet)));
             } else {
                 self.log.push(
                     &format!("Nothing to do with this thing"),
@@ -277,11 +529,20 @@ impl Client {
                 );
             }
         } else {
-            self.log.push(
-                &format!("You failed to interact with anything"),
-                Some(Color::RED),
-                None,
-            );
+            let toggles = {
+                let map = self.resources().get::<Map>().unwrap();
+                let target = position + delta;
+                map.in_bounds(target) && self.tiles.get(map.get_type(target)).toggle.is_some()
+            };
+            if toggles {
+                self.network_client.try_interact(delta);
+            } else {
+                self.log.push(
+                    &format!("You failed to interact with anything"),
+                    Some(Color::RED),
+                    None,
+                );
+            }
         }
 
     }
@@ -291,10 +552,7 @@ impl Client {
     }
 
     pub fn handle_move(&mut self, delta: impl Into<Vector>) {
-        let delta = delta.into();
-        if self.network_client.try_move_player(delta) {
-            self.camera.move_focus(delta);
-        }
+        self.network_client.try_move_player(delta);
     }
 
     pub fn handle_click(&mut self, point: impl Into<Point>) {
@@ -314,20 +572,25 @@ impl Client {
         }
         if !found {
             self.render_context.targeted_entity = None;
+            self.travel_to(point);
         }
     }
 
     pub fn render(&mut self) {
-        self.render_context.gfx.clear(Color::BLACK);
+        self.render_context.gfx.clear(Color::BLACK.into());
         self.camera.set_dimensions(self.layout.map.region.size.into());
         self.camera
-            .render(&self.network_client, &mut self.layout.map);
+            .render(&self.network_client, &self.tiles, &mut self.layout.map);
+        if let Some(travel) = &self.travel {
+            self.camera.render_path(travel.path.iter().cloned(), &mut self.layout.map);
+        }
         draw_ui(
             &mut self.layout,
             &self.network_client.world(),
             &mut self.render_context,
             &mut self.log,
-            &self.mode
+            &self.mode,
+            self.network_client.seed(),
         );
         self.layout.render(&mut self.render_context);
         self.render_context.show();
@@ -361,3 +624,113 @@ impl TileContext {
         self.tileset.draw(gfx, &glyph, rect);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{plan_path, Travel, TravelStep, MAX_TRAVEL_DISTANCE};
+    use crate::geom::{Point, Vector};
+    use crate::map::{Map, TileType};
+    use crate::message::Message;
+    use crate::network::protocol::{Request, ServerMessage};
+    use crate::tiles::TileDefinitions;
+    use legion::prelude::*;
+    use std::collections::HashSet;
+
+    /// A room of floor the player has already seen all of.
+    fn room(width: i32, height: i32) -> Map {
+        let mut map = Map::new((width, height), 0);
+        for x in 0..width {
+            for y in 0..height {
+                map.set_type(Point::new(x, y), TileType::Floor);
+                map.set_revealed(Point::new(x, y));
+            }
+        }
+        map.refresh_blocked(&TileDefinitions::load_from_path("static/data/tiles.json").unwrap());
+        map
+    }
+
+    #[test]
+    fn test_plans_around_walls_and_people() {
+        let mut map = room(5, 3);
+        map.set_type(Point::new(2, 0), TileType::Wall);
+        map.refresh_blocked(&TileDefinitions::load_from_path("static/data/tiles.json").unwrap());
+        let occupied = vec![Point::new(2, 1)].into_iter().collect::<HashSet<_>>();
+
+        let path = plan_path(&map, Point::new(0, 0), Point::new(4, 0), &occupied).unwrap();
+        assert_eq!(path.len(), 8);
+        assert_eq!(path.last(), Some(&Point::new(4, 0)));
+        assert!(path.contains(&Point::new(2, 2)));
+    }
+
+    #[test]
+    fn test_plans_only_over_known_ground() {
+        let mut map = room(5, 1);
+        map.revealed_tiles[2] = false;
+        assert_eq!(
+            plan_path(&map, Point::new(0, 0), Point::new(4, 0), &HashSet::new()),
+            None
+        );
+
+        let map = room(MAX_TRAVEL_DISTANCE + 2, 1);
+        let far = Point::new(MAX_TRAVEL_DISTANCE + 1, 0);
+        assert_eq!(
+            plan_path(&map, Point::new(0, 0), far, &HashSet::new()),
+            None
+        );
+        let near = Point::new(MAX_TRAVEL_DISTANCE, 0);
+        let path = plan_path(&map, Point::new(0, 0), near, &HashSet::new()).unwrap();
+        assert_eq!(path.len(), MAX_TRAVEL_DISTANCE as usize);
+    }
+
+    #[test]
+    fn test_travel_stops_for_events_and_failed_steps() {
+        let path = vec![Point::new(1, 0), Point::new(2, 0)];
+        let step = |success| ServerMessage::Response {
+            request: Request::Move { dx: 1, dy: 0 },
+            success,
+        };
+        let mut travel = Travel::new(path.clone(), HashSet::new());
+        travel.waiting = true;
+        assert!(travel.handle(&step(true)));
+        assert_eq!(travel.path.len(), 1);
+        assert!(!travel.waiting);
+        assert!(travel.handle(&ServerMessage::Response {
+            request: Request::Wait,
+            success: true,
+        }));
+
+        let event = Message::GameEvent("A customer walks in.".to_string(), None, None);
+        assert!(!travel.handle(&ServerMessage::Event(event)));
+        assert!(!Travel::new(path, HashSet::new()).handle(&step(false)));
+    }
+
+    #[test]
+    fn test_continue_travel() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let entities = world.insert((), vec![(1u32,), (2u32,)]).to_vec();
+        let seen = vec![entities[0]].into_iter().collect::<HashSet<_>>();
+        let mut travel = Travel::new(vec![Point::new(1, 0)], seen.clone());
+        let from = Point::new(0, 0);
+
+        assert_eq!(
+            travel.next_step(from, &seen),
+            TravelStep::Move(Vector::new(1, 0))
+        );
+        assert_eq!(
+            travel.next_step(from, &HashSet::new()),
+            TravelStep::Move(Vector::new(1, 0))
+        );
+        let everyone = entities.iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(travel.next_step(from, &everyone), TravelStep::Spotted);
+
+        travel.waiting = true;
+        assert_eq!(travel.next_step(from, &seen), TravelStep::Wait);
+        travel.waiting = false;
+        travel.path.clear();
+        assert_eq!(
+            travel.next_step(Point::new(1, 0), &seen),
+            TravelStep::Arrived
+        );
+    }
+}
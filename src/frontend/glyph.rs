@@ -1,10 +1,12 @@
-use crate::color::GREY;
-use quicksilver::graphics::Color;
+use crate::color::{Color, GREY};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Glyph {
     pub ch: char,
+    #[serde(with = "crate::color::option_color")]
     pub foreground: Option<Color>,
+    #[serde(with = "crate::color::option_color")]
     pub background: Option<Color>,
     pub render_order: i32,
 }
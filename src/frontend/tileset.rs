@@ -1,3 +1,4 @@
+use crate::color::Color;
 use crate::frontend::font::Font;
 use crate::frontend::glyph::Glyph;
 
@@ -6,6 +7,17 @@ use crate::geom::{Rect, To};
 use quicksilver::graphics::{Graphics, Image};
 use std::collections::HashMap;
 
+impl From<Color> for quicksilver::graphics::Color {
+    fn from(color: Color) -> Self {
+        quicksilver::graphics::Color {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+            a: color.a,
+        }
+    }
+}
+
 pub struct Tileset {
     image: Image,
     map: HashMap<char, Rect>,
@@ -39,14 +51,14 @@ impl Tileset {
         let image = &self.image;
         let region = region.to();
         if let Some(background) = &glyph.background {
-            gfx.fill_rect(&region, *background);
+            gfx.fill_rect(&region, (*background).into());
         }
         if glyph.ch == ' ' {
             return;
         }
         let rect = self.map[&glyph.ch];
         if let Some(foreground) = &glyph.foreground {
-            gfx.draw_subimage_tinted(image, rect.to(), region, *foreground);
+            gfx.draw_subimage_tinted(image, rect.to(), region, (*foreground).into());
         } else {
             gfx.draw_subimage(image, rect.to(), region);
         }
@@ -2,12 +2,13 @@ use crate::frontend::client::RenderContext;
 use crate::frontend::glyph::Glyph;
 
 use super::{client::{UIMode, LayoutManager, Interactable}, screen::terminal::Terminal};
-use crate::geom::{Point, Rect};
+use crate::color::Color;
+use crate::geom::{Point, Rect, Vector};
 use crate::{
-    resources::log::GameLog, client::network_client::NetworkClient,
+    resources::log::GameLog, client::network_client::NetworkClient, component,
 };
 use legion::prelude::*;
-use quicksilver::{lifecycle::Key, graphics::Color};
+use quicksilver::lifecycle::Key;
 use super::client::{UIWidget, UITransition};
 use std::char::from_digit;
 
@@ -88,17 +89,20 @@ pub struct InventoryWidget {
 }
 
 pub struct DisplayCaseWidget {
-    case: Entity,
+    direction: Vector,
     contents: Vec<InventoryEntry>,
     player_inventory: Vec<InventoryEntry>,
+    /// Cabinets hold the player's own stock; anything else has to be bought and sold.
+    cabinet: bool,
 }
 
 impl DisplayCaseWidget {
-    pub fn new(case: Entity, contents: Vec<InventoryEntry>, player_inventory: Vec<InventoryEntry>) -> Self {
+    pub fn new(direction: Vector, contents: Vec<InventoryEntry>, player_inventory: Vec<InventoryEntry>, cabinet: bool) -> Self {
         DisplayCaseWidget {
-            case,
+            direction,
             contents,
-            player_inventory
+            player_inventory,
+            cabinet
         }
     }
 }
@@ -122,11 +126,19 @@ impl UIWidget for DisplayCaseWidget {}
 impl Interactable for DisplayCaseWidget {
     fn handle_key(&mut self, key: quicksilver::lifecycle::Key, client: &mut NetworkClient) -> UITransition {
         match key {
-            Key::T => {
+            Key::B if !self.cabinet => {
+                // The server says whether the sale went through, so there's nothing to
+                // report here.
                 if !self.contents.is_empty() {
-                    client.try_player_take(self.case);
+                    client.try_player_buy(self.direction);
+                }
+                UITransition::Exit
+            }
+            Key::T if self.cabinet => {
+                if !self.contents.is_empty() {
+                    client.try_player_take(self.direction);
                     UITransition::Switch(Box::new(MessageWidget{
-                    message: format!("You took the {:?}",  self.contents.first().unwrap().display_name)
+                    message: format!("You took the {:?}",  self.contents.last().unwrap().display_name)
                 }))
                 } else {
                     UITransition::Exit
@@ -135,11 +147,18 @@ impl Interactable for DisplayCaseWidget {
             key => {
                 if self.contents.is_empty() {
                     let key = key_to_char(key);
-                    if let Some(choice) = get_entity(&self.player_inventory, key) {
-                        client.try_player_put(self.case, choice.entity);
-                        return UITransition::Switch(Box::new(MessageWidget{
-                            message: format!("You put the {:?}",choice.display_name)
-                        }))
+                    let choice = entity_enum(&self.player_inventory)
+                        .into_iter()
+                        .enumerate()
+                        .find(|(_, (c, _))| *c == key);
+                    if let Some((slot, (_, choice))) = choice {
+                        if self.cabinet {
+                            client.try_player_put(self.direction, slot);
+                            return UITransition::Switch(Box::new(MessageWidget{
+                                message: format!("You put the {:?}",choice.display_name)
+                            }))
+                        }
+                        client.try_player_sell(self.direction, slot);
                     }
                 }
                 UITransition::Exit
@@ -154,11 +173,14 @@ impl UIElement for DisplayCaseWidget {
         region.origin = (0, 0).into();
         draw_box_filled(terminal, region, None, Some(Color::BLACK));
         if !self.contents.is_empty() {
-            print(terminal, &format!("Press T to take the"), (1, 1), None, Some(Color::BLACK));
-            print(terminal, &format!("'{:?}'", self.contents.first().unwrap().display_name), (1, 2), None, Some(Color::BLACK))
+            let verb = if self.cabinet { "Press T to take the" } else { "Press B to buy the" };
+            print(terminal, verb, (1, 1), None, Some(Color::BLACK));
+            // Whatever went in last is on top, and that's what comes out first.
+            print(terminal, &format!("'{:?}'", self.contents.last().unwrap().display_name), (1, 2), None, Some(Color::BLACK))
         } else {
 
-            print(terminal, &format!("To put press:"), (1, 1), None, Some(Color::BLACK));
+            let verb = if self.cabinet { "To put press:" } else { "To sell press:" };
+            print(terminal, verb, (1, 1), None, Some(Color::BLACK));
             for (index, (c, entry)) in entity_enum(&self.player_inventory).iter().enumerate() {
                 print(terminal, &format!("[{}] {}", c, entry.display_name), (1, (2 + index) as i32), None, Some(Color::BLACK));
             }
@@ -169,10 +191,11 @@ impl UIElement for DisplayCaseWidget {
 
 pub fn draw_ui(
     layout: &mut LayoutManager,
-    _: &World,
+    world: &World,
     _: &RenderContext,
     game_log: &GameLog,
     mode: &UIMode,
+    seed: Option<u64>,
 ) {
     let LayoutManager {
         main,
@@ -207,7 +230,14 @@ pub fn draw_ui(
         },
         _ => {}
     }
-    print(log, VERSION, (1, 1), None, None);
+    let query = <Read<component::Wallet>>::query().filter(tag::<component::Player>());
+    if let Some(wallet) = query.iter(world).next() {
+        print(player, &format!("Coins: {}", wallet.coins), (1, 1), Some(Color::YELLOW), None);
+    }
+    match seed {
+        Some(seed) => print(log, &format!("{} seed {}", VERSION, seed), (1, 1), None, None),
+        None => print(log, VERSION, (1, 1), None, None),
+    }
 
 
 }
@@ -0,0 +1,16 @@
+#![feature(vec_remove_item)]
+
+pub mod client;
+pub mod color;
+pub mod common;
+pub mod component;
+pub mod dijkstra_map;
+pub mod error;
+pub mod frontend;
+pub mod geom;
+pub mod map;
+pub mod message;
+pub mod network;
+pub mod resources;
+pub mod server;
+pub mod tiles;
@@ -1,22 +1,12 @@
-#![feature(vec_remove_item)]
-use crate::server::server::Server;
+use four_am::client::network_client::NetworkClient;
+use four_am::frontend;
+use four_am::network::tcp::TcpClientTransport;
+use four_am::server::server::{Server, ServerSettings};
 use instant::Instant;
 use quicksilver::graphics::Graphics;
 use quicksilver::lifecycle::{run, EventStream, Settings, Window};
 use quicksilver::Result;
 
-pub mod client;
-pub mod color;
-pub mod common;
-pub mod component;
-pub mod error;
-pub mod frontend;
-pub mod geom;
-pub mod map;
-pub mod message;
-pub mod resources;
-pub mod server;
-
 fn main() {
     run(
         Settings {
@@ -75,25 +65,51 @@ impl TimeStep {
     }
 }
 
+const USAGE: &str =
+    "usage: four-am [--seed <seed>] [--show-mapgen] [--connect <address>] [--record <file>]";
+
+/// Reports a bad command line and exits, rather than panicking with a backtrace.
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", USAGE, message);
+    std::process::exit(2);
+}
+
+fn argument(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+fn server_settings() -> ServerSettings {
+    ServerSettings {
+        seed: argument("--seed").map(|seed| {
+            seed.parse()
+                .unwrap_or_else(|_| usage_error("--seed must be a number"))
+        }),
+        show_map_generation: std::env::args().any(|arg| arg == "--show-mapgen"),
+    }
+}
+
 async fn app(window: Window, gfx: Graphics, events: EventStream) -> Result<()> {
     let mut timestep = TimeStep::new();
     let mut lag: f32 = 0.0;
     let mut turns = 0;
-    let mut server = Server::new().await;
-    let mut client = frontend::client::Client::new(window, gfx, events).await;
-    client.network_client.bind(server);
-    client.sync();
-    server = client.network_client.unbind();
+    // `--connect <address>` plays against a headless server instead of an in-process one.
+    let mut network_client = match argument("--connect") {
+        Some(address) => NetworkClient::remote(Box::new(
+            TcpClientTransport::connect(address).expect("Couldn't connect to server"),
+        )),
+        None => NetworkClient::local(Server::new(server_settings()).await),
+    };
+    if let Some(path) = argument("--record") {
+        network_client.record_to(path);
+    }
+    let mut client = frontend::client::Client::new(window, gfx, events, network_client).await;
     let mut first = true;
     loop {
-        // For now do this bind/unbind song and dance until fully refactored
-        client.network_client.bind(server);
         client.tick().await;
-        server = client.network_client.unbind();
         lag += timestep.delta();
         while lag >= MS_PER_UPDATE {
             turns += 1;
-            server.tick();
+            client.network_client.tick_server();
             lag -= MS_PER_UPDATE;
         }
         if let Some(fps) = timestep.frame_rate() {
@@ -101,14 +117,10 @@ async fn app(window: Window, gfx: Graphics, events: EventStream) -> Result<()> {
             println!("TPS {}", turns);
             turns = 0;
         }
-        let messages = server.messages();
-        client.network_client.bind(server);
+        client.process_messages();
         if first {
-            client.sync();
-            first = false;
+            first = !client.sync();
         }
-        client.process_messages(messages);
         client.render();
-        server = client.network_client.unbind();
     }
 }
@@ -1,11 +1,21 @@
+use crate::color::Color;
 use crate::geom::{Point, Vector};
+use crate::tiles::TileDefinitions;
 use legion::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum TileType {
     Wall,
     Floor,
     Digging,
+    Entrance,
+    DownStairs,
+    UpStairs,
+    DoorClosed,
+    DoorOpen,
+    Counter,
+    Window,
 }
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -47,14 +57,20 @@ impl TilePos {
     }
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Map {
     pub tiles: Vec<TileType>,
     pub size: Vector,
     pub revealed_tiles: Vec<bool>,
     pub visible_tiles: Vec<bool>,
     pub blocked: Vec<bool>,
+    /// Tiles that can't be seen through.
+    pub opaque: Vec<bool>,
+    /// Red, green and blue light falling on every tile, added up over all light sources.
+    pub light: Vec<[f32; 3]>,
     pub depth: i32,
+    // Rebuilt every tick by the index system, so it never needs to leave the server.
+    #[serde(skip)]
     pub tile_content: Vec<Option<Entity>>,
 }
 
@@ -69,6 +85,8 @@ impl Map {
             revealed_tiles: vec![false; total],
             visible_tiles: vec![false; total],
             blocked: vec![true; total],
+            opaque: vec![true; total],
+            light: vec![[0.0; 3]; total],
             depth,
             tile_content: vec![None; total],
         }
@@ -81,10 +99,46 @@ impl Map {
         ((point.y as usize) * self.size.x as usize) + point.x as usize
     }
 
+    pub fn index_to_point(&self, index: usize) -> Point {
+        let width = self.size.x as usize;
+        ((index % width) as i32, (index / width) as i32).into()
+    }
+
+    /// The first tile of the given type, scanning row by row.
+    pub fn find(&self, tile_type: TileType) -> Option<Point> {
+        self.tiles
+            .iter()
+            .position(|tile| *tile == tile_type)
+            .map(|index| self.index_to_point(index))
+    }
+
+    /// Where customers come into the level and leave it again.
+    pub fn entrance(&self) -> Option<Point> {
+        self.find(TileType::Entrance)
+    }
+
+    pub fn in_bounds(&self, point: Point) -> bool {
+        point.x >= 0 && point.y >= 0 && point.x < self.size.x && point.y < self.size.y
+    }
+
+    /// The blocking entity standing on `point`, as of the last index pass.
+    pub fn content_at(&self, point: Point) -> Option<Entity> {
+        if !self.in_bounds(point) {
+            return None;
+        }
+        self.tile_content[self.point_to_index(point)]
+    }
+
     pub fn is_blocked(&self, point: Point) -> bool {
         self.blocked[self.coord_to_index(point.x, point.y)]
     }
 
+    /// Whether the tile at `point` stops anything being seen past it, as of the last
+    /// `refresh_blocked`.
+    pub fn blocks_sight(&self, point: Point) -> bool {
+        self.opaque[self.point_to_index(point)]
+    }
+
     pub fn get_type(&self, point: Point) -> TileType {
         self.tiles[self.point_to_index(point)]
     }
@@ -110,14 +164,29 @@ impl Map {
         self.revealed_tiles[index] = true;
     }
 
-    pub fn refresh_blocked(&mut self) {
-        for (i, tile) in self.tiles.iter_mut().enumerate() {
-            self.blocked[i] = *tile == TileType::Wall;
+    pub fn clear_light(&mut self) {
+        for level in self.light.iter_mut() {
+            *level = [0.0; 3];
         }
     }
-    pub fn refresh_content(&mut self) {
-        for i in 0..self.tile_content.len() {
-            self.tile_content[i] = None
+
+    pub fn add_light(&mut self, point: Point, color: Color, amount: f32) {
+        let index = self.point_to_index(point);
+        let level = &mut self.light[index];
+        level[0] += color.r * amount;
+        level[1] += color.g * amount;
+        level[2] += color.b * amount;
+    }
+
+    pub fn refresh_blocked(&mut self, definitions: &TileDefinitions) {
+        for (i, tile) in self.tiles.iter().enumerate() {
+            let definition = definitions.get(*tile);
+            self.blocked[i] = definition.blocks_movement;
+            self.opaque[i] = definition.blocks_sight;
         }
     }
+    pub fn refresh_content(&mut self) {
+        self.tile_content.clear();
+        self.tile_content.resize(self.tiles.len(), None);
+    }
 }
@@ -1,5 +1,11 @@
-use quicksilver::graphics::Color;
+use crate::color::Color;
+use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
-    GameEvent(String, Option<Color>, Option<Color>),
+    GameEvent(
+        String,
+        #[serde(with = "crate::color::option_color")] Option<Color>,
+        #[serde(with = "crate::color::option_color")] Option<Color>,
+    ),
 }
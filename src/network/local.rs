@@ -0,0 +1,101 @@
+use crate::network::protocol::{Request, ServerMessage};
+use crate::network::{ClientTransport, ServerTransport};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+pub struct LocalClientTransport {
+    sender: Sender<Request>,
+    receiver: Receiver<ServerMessage>,
+}
+
+pub struct LocalServerTransport {
+    sender: Sender<ServerMessage>,
+    receiver: Receiver<Request>,
+    connected: bool,
+}
+
+/// Creates both ends of an in-process connection. Neither end blocks, so the pair can
+/// be driven from a single thread (which is all we get on the web).
+pub fn pair() -> (LocalClientTransport, LocalServerTransport) {
+    let (request_sender, request_receiver) = unbounded();
+    let (message_sender, message_receiver) = unbounded();
+    (
+        LocalClientTransport {
+            sender: request_sender,
+            receiver: message_receiver,
+        },
+        LocalServerTransport {
+            sender: message_sender,
+            receiver: request_receiver,
+            connected: true,
+        },
+    )
+}
+
+impl ClientTransport for LocalClientTransport {
+    fn send(&mut self, request: Request) {
+        self.sender.send(request).expect("Local server hung up");
+    }
+
+    fn receive(&mut self) -> Vec<ServerMessage> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl ServerTransport for LocalServerTransport {
+    fn send(&mut self, message: ServerMessage) {
+        // The client going away isn't the server's problem.
+        let _ = self.sender.send(message);
+    }
+
+    fn receive(&mut self) -> Vec<Request> {
+        self.receiver.try_iter().collect()
+    }
+
+    fn new_connection(&mut self) -> bool {
+        std::mem::replace(&mut self.connected, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pair;
+    use crate::network::protocol::{Request, ServerMessage};
+    use crate::network::{ClientTransport, ServerTransport};
+
+    #[test]
+    fn test_messages_arrive_in_order() {
+        let (mut client, mut server) = pair();
+        assert!(server.new_connection());
+        assert!(!server.new_connection());
+        assert!(server.receive().is_empty());
+
+        client.send(Request::Move { dx: 0, dy: 1 });
+        client.send(Request::Take { dx: 1, dy: 0 });
+        assert_eq!(
+            server.receive(),
+            vec![
+                Request::Move { dx: 0, dy: 1 },
+                Request::Take { dx: 1, dy: 0 }
+            ]
+        );
+        assert!(server.receive().is_empty());
+
+        server.send(ServerMessage::Response {
+            request: Request::Wait,
+            success: false,
+        });
+        assert_eq!(client.receive().len(), 1);
+        assert!(client.receive().is_empty());
+    }
+
+    #[test]
+    fn test_server_outlives_its_client() {
+        let (client, mut server) = pair();
+        drop(client);
+        server.send(ServerMessage::Response {
+            request: Request::Wait,
+            success: true,
+        });
+        assert!(server.receive().is_empty());
+    }
+}
@@ -0,0 +1,18 @@
+use crate::network::protocol::{Request, ServerMessage};
+
+pub mod local;
+pub mod protocol;
+pub mod tcp;
+
+pub trait ClientTransport {
+    fn send(&mut self, request: Request);
+    fn receive(&mut self) -> Vec<ServerMessage>;
+}
+
+pub trait ServerTransport {
+    fn send(&mut self, message: ServerMessage);
+    fn receive(&mut self) -> Vec<Request>;
+    /// Returns true once for every client that connected since the last call, so the
+    /// host knows when to send a full snapshot.
+    fn new_connection(&mut self) -> bool;
+}
@@ -0,0 +1,113 @@
+use crate::component::{Name, Position, Renderable, Value, Wallet};
+use crate::map::{Map, TileType};
+use crate::message::Message;
+use serde::{Deserialize, Serialize};
+
+/// Everything a client can ask the server to do. Targets are given as an offset from
+/// the player so requests never need to name server-side entities.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    Move { dx: i32, dy: i32 },
+    Wait,
+    Interact { dx: i32, dy: i32 },
+    Take { dx: i32, dy: i32 },
+    Put { dx: i32, dy: i32, slot: usize },
+    Buy { dx: i32, dy: i32 },
+    Sell { dx: i32, dy: i32, slot: usize },
+    /// Takes the stairs the player is standing on.
+    Descend,
+    Ascend,
+    Save,
+    Load,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Response { request: Request, success: bool },
+    Event(Message),
+    Snapshot(WorldSnapshot),
+    Delta(WorldDelta),
+}
+
+/// Stable name for a server-side entity. Legion entities are only meaningful inside the
+/// world that created them, so these are what gets sent to clients instead.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct NetworkId(pub u32);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InventoryState {
+    pub contents: Vec<NetworkId>,
+    pub capacity: u8,
+}
+
+/// Everything a client gets to know about a single entity.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EntityState {
+    pub id: NetworkId,
+    pub name: Name,
+    pub position: Option<Position>,
+    pub renderable: Option<Renderable>,
+    pub inventory: Option<InventoryState>,
+    pub value: Option<Value>,
+    pub wallet: Option<Wallet>,
+    pub player: bool,
+    pub display_cabinet: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub seed: u64,
+    pub entities: Vec<EntityState>,
+    pub map: Map,
+}
+
+/// Everything the map stores about one tile.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileState {
+    pub index: usize,
+    pub tile: TileType,
+    pub revealed: bool,
+    pub visible: bool,
+    pub blocked: bool,
+    pub opaque: bool,
+    pub light: [f32; 3],
+}
+
+impl TileState {
+    pub fn capture(map: &Map, index: usize) -> Self {
+        TileState {
+            index,
+            tile: map.tiles[index],
+            revealed: map.revealed_tiles[index],
+            visible: map.visible_tiles[index],
+            blocked: map.blocked[index],
+            opaque: map.opaque[index],
+            light: map.light[index],
+        }
+    }
+
+    pub fn apply(&self, map: &mut Map) {
+        map.tiles[self.index] = self.tile;
+        map.revealed_tiles[self.index] = self.revealed;
+        map.visible_tiles[self.index] = self.visible;
+        map.blocked[self.index] = self.blocked;
+        map.opaque[self.index] = self.opaque;
+        map.light[self.index] = self.light;
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MapUpdate {
+    /// A different level, or the first one.
+    Whole(Map),
+    /// Only the tiles that changed on the level the client already has.
+    Tiles(Vec<TileState>),
+}
+
+/// Changes since the last snapshot or delta. Changed entities are sent whole.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldDelta {
+    pub changed: Vec<EntityState>,
+    pub removed: Vec<NetworkId>,
+    pub map: Option<MapUpdate>,
+}
@@ -0,0 +1,246 @@
+use crate::network::protocol::{Request, ServerMessage};
+use crate::network::{ClientTransport, ServerTransport};
+use crossbeam_channel::{unbounded, Receiver};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+
+// Messages are sent as one JSON document per line. Reading happens on a background
+// thread per connection so neither side ever blocks its game loop waiting for input.
+
+fn spawn_reader<T: DeserializeOwned + Send + 'static>(stream: TcpStream) -> Receiver<T> {
+    let (sender, receiver) = unbounded();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            match serde_json::from_str(&line) {
+                Ok(message) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => println!("Dropping malformed message: {}", err),
+            }
+        }
+    });
+    receiver
+}
+
+fn write_message<T: Serialize>(stream: &mut TcpStream, message: &T) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)
+}
+
+pub struct TcpClientTransport {
+    stream: TcpStream,
+    receiver: Receiver<ServerMessage>,
+}
+
+impl TcpClientTransport {
+    pub fn connect(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        let receiver = spawn_reader(stream.try_clone()?);
+        Ok(TcpClientTransport { stream, receiver })
+    }
+}
+
+impl ClientTransport for TcpClientTransport {
+    fn send(&mut self, request: Request) {
+        if let Err(err) = write_message(&mut self.stream, &request) {
+            println!("Failed to send {:?}: {}", request, err);
+        }
+    }
+
+    fn receive(&mut self) -> Vec<ServerMessage> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    receiver: Receiver<Request>,
+}
+
+/// Serves a single client at a time; a new connection replaces the previous one.
+pub struct TcpServerTransport {
+    listener: TcpListener,
+    connection: Option<Connection>,
+    connected: bool,
+}
+
+impl TcpServerTransport {
+    pub fn bind(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(TcpServerTransport {
+            listener,
+            connection: None,
+            connected: false,
+        })
+    }
+
+    /// The address actually bound, which is how to find the port after binding port 0.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    fn accept(&mut self) {
+        if let Ok((stream, address)) = self.listener.accept() {
+            let connection = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_nodelay(true))
+                .and_then(|_| stream.try_clone());
+            match connection {
+                Ok(reader) => {
+                    println!("Client connected from {}", address);
+                    self.connection = Some(Connection {
+                        stream,
+                        receiver: spawn_reader(reader),
+                    });
+                    self.connected = true;
+                }
+                Err(err) => println!("Failed to accept {}: {}", address, err),
+            }
+        }
+    }
+}
+
+impl ServerTransport for TcpServerTransport {
+    fn send(&mut self, message: ServerMessage) {
+        let failed = match &mut self.connection {
+            Some(connection) => write_message(&mut connection.stream, &message).is_err(),
+            None => false,
+        };
+        if failed {
+            println!("Client disconnected");
+            self.connection = None;
+        }
+    }
+
+    fn receive(&mut self) -> Vec<Request> {
+        self.accept();
+        match &self.connection {
+            Some(connection) => connection.receiver.try_iter().collect(),
+            None => vec![],
+        }
+    }
+
+    fn new_connection(&mut self) -> bool {
+        self.accept();
+        std::mem::replace(&mut self.connected, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TcpClientTransport, TcpServerTransport};
+    use crate::network::protocol::{Request, ServerMessage};
+    use crate::network::{ClientTransport, ServerTransport};
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Retries `ready` for up to two seconds, since the other end runs on its own thread.
+    fn eventually(mut ready: impl FnMut() -> bool) -> bool {
+        for _ in 0..400 {
+            if ready() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    /// Collects from `receive` until `count` messages have come in or it times out.
+    fn receive_n<T>(count: usize, mut receive: impl FnMut() -> Vec<T>) -> Vec<T> {
+        let mut received = vec![];
+        eventually(|| {
+            received.extend(receive());
+            received.len() >= count
+        });
+        received
+    }
+
+    fn connect(server: &mut TcpServerTransport) -> TcpClientTransport {
+        let client = TcpClientTransport::connect(server.local_addr().unwrap()).unwrap();
+        assert!(eventually(|| server.new_connection()));
+        client
+    }
+
+    fn response() -> ServerMessage {
+        ServerMessage::Response {
+            request: Request::Wait,
+            success: true,
+        }
+    }
+
+    #[test]
+    fn test_messages_cross_a_loopback_connection() {
+        let mut server = TcpServerTransport::bind("127.0.0.1:0").unwrap();
+        let mut client = connect(&mut server);
+        assert!(!server.new_connection());
+
+        client.send(Request::Move { dx: 1, dy: -1 });
+        client.send(Request::Wait);
+        let requests = receive_n(2, || server.receive());
+        assert_eq!(
+            requests,
+            vec![Request::Move { dx: 1, dy: -1 }, Request::Wait]
+        );
+
+        server.send(response());
+        match receive_n(1, || client.receive()).as_slice() {
+            [ServerMessage::Response { request, success }] => {
+                assert_eq!(*request, Request::Wait);
+                assert!(*success);
+            }
+            other => panic!("unexpected messages {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_partial_and_malformed_lines() {
+        let mut server = TcpServerTransport::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        assert!(eventually(|| server.new_connection()));
+
+        stream.write_all(b"{\"Move\":{\"dx\":2,").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert!(server.receive().is_empty());
+
+        // The rest of the line, then one that can't be read, which is skipped.
+        let rest: &[u8] = b"\"dy\":0}}\nnot json\n\"Wait\"\n";
+        stream.write_all(rest).unwrap();
+        let requests = receive_n(2, || server.receive());
+        assert_eq!(
+            requests,
+            vec![Request::Move { dx: 2, dy: 0 }, Request::Wait]
+        );
+    }
+
+    #[test]
+    fn test_disconnect_and_reconnect() {
+        let mut server = TcpServerTransport::bind("127.0.0.1:0").unwrap();
+        drop(connect(&mut server));
+
+        // The first writes after a hang up can still land in the socket buffer, so keep
+        // sending until the server notices.
+        assert!(eventually(|| {
+            server.send(response());
+            server.connection.is_none()
+        }));
+        assert!(server.receive().is_empty());
+
+        let mut client = connect(&mut server);
+        client.send(Request::Descend);
+        assert_eq!(receive_n(1, || server.receive()), vec![Request::Descend]);
+    }
+}
@@ -1,6 +1,6 @@
 pub mod log {
+    use crate::color::Color;
     use crate::frontend::glyph::Glyph;
-    use quicksilver::graphics::Color;
     use std::slice::Iter;
 
     pub struct GameLog {
@@ -0,0 +1,88 @@
+use crate::network::protocol::Request;
+use crate::server::systems::turn_system::cost;
+use legion::prelude::Entity;
+
+/// Something an entity wants to do on its turn. Directions are relative to the acting
+/// entity so players and AI describe actions the same way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Move { dx: i32, dy: i32 },
+    Wait,
+    Interact { dx: i32, dy: i32 },
+    Inspect { dx: i32, dy: i32 },
+    Take { dx: i32, dy: i32 },
+    Put { dx: i32, dy: i32, slot: usize },
+    /// Pays for the item on top of a display cabinet or crate.
+    Buy { dx: i32, dy: i32 },
+    /// Sells an item into a display cabinet or crate.
+    Sell { dx: i32, dy: i32, slot: usize },
+    /// Takes the stairs the actor is standing on.
+    Descend,
+    Ascend,
+}
+
+impl Action {
+    /// The game action behind a client request, if there is one.
+    pub fn from_request(request: Request) -> Option<Action> {
+        match request {
+            Request::Move { dx, dy } => Some(Action::Move { dx, dy }),
+            Request::Wait => Some(Action::Wait),
+            Request::Interact { dx, dy } => Some(Action::Interact { dx, dy }),
+            Request::Take { dx, dy } => Some(Action::Take { dx, dy }),
+            Request::Put { dx, dy, slot } => Some(Action::Put { dx, dy, slot }),
+            Request::Buy { dx, dy } => Some(Action::Buy { dx, dy }),
+            Request::Sell { dx, dy, slot } => Some(Action::Sell { dx, dy, slot }),
+            Request::Descend => Some(Action::Descend),
+            Request::Ascend => Some(Action::Ascend),
+            Request::Save | Request::Load => None,
+        }
+    }
+
+    pub fn cost(&self) -> i32 {
+        match self {
+            Action::Move { .. } => cost::MOVE,
+            Action::Wait => cost::WAIT,
+            Action::Interact { .. } => cost::INTERACT,
+            Action::Inspect { .. } => cost::INSPECT,
+            Action::Take { .. } => cost::TAKE,
+            Action::Put { .. } => cost::PUT,
+            Action::Buy { .. } => cost::BUY,
+            Action::Sell { .. } => cost::SELL,
+            Action::Descend | Action::Ascend => cost::STAIRS,
+        }
+    }
+
+    /// Which way the action points, for actions aimed at a neighbouring tile.
+    pub fn direction(&self) -> Option<(i32, i32)> {
+        match *self {
+            Action::Move { dx, dy }
+            | Action::Interact { dx, dy }
+            | Action::Inspect { dx, dy }
+            | Action::Take { dx, dy }
+            | Action::Put { dx, dy, .. }
+            | Action::Buy { dx, dy }
+            | Action::Sell { dx, dy, .. } => Some((dx, dy)),
+            Action::Wait | Action::Descend | Action::Ascend => None,
+        }
+    }
+}
+
+/// An action waiting for its entity's turn. The action system is the only thing that
+/// carries them out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intent {
+    pub action: Action,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ActionResult {
+    pub actor: Entity,
+    pub action: Action,
+    pub success: bool,
+}
+
+/// Outcomes of every action carried out since the last time they were collected.
+#[derive(Default)]
+pub struct ActionResults {
+    pub results: Vec<ActionResult>,
+}
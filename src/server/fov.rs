@@ -1,37 +1,252 @@
 use crate::geom::Point;
 use crate::map::Map;
-use std::collections::HashSet;
-
-pub fn calculate_fov(pos: Point, radius: u32, map: &Map) -> HashSet<Point> {
-    let mut set = HashSet::new();
-    for i in 0..361 {
-        calculate_fov_for_direction(
-            pos,
-            radius,
-            ((i as f32).to_radians().cos(), (i as f32).to_radians().sin()),
-            map,
-            &mut set,
-        );
-    }
-    set
-}
-
-fn calculate_fov_for_direction(
-    pos: Point,
-    radius: u32,
-    direction: (f32, f32),
-    map: &Map,
-    set: &mut HashSet<Point>,
-) {
-    let mut ox = (pos.x as f32) + 0.5;
-    let mut oy = (pos.y as f32) + 0.5;
-    for _i in 0..radius {
-        set.insert((ox as i32, oy as i32).into());
-        let coords = map.coord_to_index(ox as i32, oy as i32);
-        if map.blocked[coords] {
-            return;
-        }
-        ox += direction.0;
-        oy += direction.1;
+
+// Symmetric shadowcasting, after https://www.albertford.com/shadowcasting/
+// Slopes are kept as exact fractions so results don't depend on float rounding.
+
+/// A slope of `num / den` columns per row. `den` is always positive.
+#[derive(Clone, Copy, Debug)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+impl Slope {
+    fn new(num: i32, den: i32) -> Self {
+        Slope { num, den }
+    }
+
+    /// The slope through the near-side edge of `col` on row `depth`.
+    fn through_edge(depth: i32, col: i32) -> Self {
+        Slope::new(2 * col - 1, 2 * depth)
+    }
+
+    /// `depth * slope`, rounded to the nearest column with halves going up.
+    fn round_ties_up(self, depth: i32) -> i32 {
+        (2 * depth * self.num + self.den).div_euclid(2 * self.den)
+    }
+
+    /// `depth * slope`, rounded to the nearest column with halves going down.
+    fn round_ties_down(self, depth: i32) -> i32 {
+        -(self.den - 2 * depth * self.num).div_euclid(2 * self.den)
+    }
+}
+
+/// One of the four 90 degree wedges the area around the origin is scanned in. Rows
+/// move away from the origin and columns run across them.
+#[derive(Clone, Copy)]
+enum Quadrant {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Quadrant {
+    fn transform(self, origin: Point, depth: i32, col: i32) -> Point {
+        match self {
+            Quadrant::North => Point::new(origin.x + col, origin.y - depth),
+            Quadrant::South => Point::new(origin.x + col, origin.y + depth),
+            Quadrant::East => Point::new(origin.x + depth, origin.y + col),
+            Quadrant::West => Point::new(origin.x - depth, origin.y + col),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+
+impl Row {
+    fn next(self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            ..self
+        }
+    }
+
+    /// Whether the centre of `col` lies inside the row's slopes. Floor tiles are only
+    /// visible when it does, which is what makes sight symmetric.
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num
+            && col * self.end.den <= self.depth * self.end.num
+    }
+}
+
+fn blocks_sight(map: &Map, point: Point) -> bool {
+    !map.in_bounds(point) || map.blocks_sight(point)
+}
+
+fn in_radius(origin: Point, point: Point, radius: i32) -> bool {
+    let (dx, dy) = (point.x - origin.x, point.y - origin.y);
+    dx * dx + dy * dy <= radius * radius
+}
+
+/// Scratch space for working out fields of view, kept between calls so that doing it for
+/// every viewshed and light each turn doesn't allocate.
+#[derive(Default)]
+pub struct FieldOfView {
+    rows: Vec<Row>,
+    /// For each map tile, the last scan that saw it. Tiles on the diagonals belong to two
+    /// quadrants and this is what stops them being seen twice.
+    seen: Vec<u32>,
+    scan: u32,
+    visible: Vec<Point>,
+}
+
+impl FieldOfView {
+    /// Every tile visible from `origin`, within a circle of `radius` tiles. Anything
+    /// outside the map blocks sight and is never included.
+    pub fn visible(&mut self, origin: Point, radius: i32, map: &Map) -> &[Point] {
+        self.visible.clear();
+        if !map.in_bounds(origin) {
+            return &self.visible;
+        }
+        if self.seen.len() != map.tiles.len() || self.scan == u32::MAX {
+            self.seen.clear();
+            self.seen.resize(map.tiles.len(), 0);
+            self.scan = 0;
+        }
+        self.scan += 1;
+        self.visible.push(origin);
+        self.seen[map.point_to_index(origin)] = self.scan;
+
+        for quadrant in &[
+            Quadrant::North,
+            Quadrant::East,
+            Quadrant::South,
+            Quadrant::West,
+        ] {
+            self.rows.push(Row {
+                depth: 1,
+                start: Slope::new(-1, 1),
+                end: Slope::new(1, 1),
+            });
+            while let Some(mut row) = self.rows.pop() {
+                if row.depth > radius {
+                    continue;
+                }
+                let mut previous_blocks = None;
+                let min_col = row.start.round_ties_up(row.depth);
+                let max_col = row.end.round_ties_down(row.depth);
+                for col in min_col..=max_col {
+                    let point = quadrant.transform(origin, row.depth, col);
+                    let blocks = blocks_sight(map, point);
+                    if (blocks || row.is_symmetric(col))
+                        && map.in_bounds(point)
+                        && in_radius(origin, point, radius)
+                    {
+                        let index = map.point_to_index(point);
+                        if self.seen[index] != self.scan {
+                            self.seen[index] = self.scan;
+                            self.visible.push(point);
+                        }
+                    }
+                    if previous_blocks == Some(true) && !blocks {
+                        row.start = Slope::through_edge(row.depth, col);
+                    }
+                    if previous_blocks == Some(false) && blocks {
+                        let mut next = row.next();
+                        next.end = Slope::through_edge(row.depth, col);
+                        self.rows.push(next);
+                    }
+                    previous_blocks = Some(blocks);
+                }
+                if previous_blocks == Some(false) {
+                    self.rows.push(row.next());
+                }
+            }
+        }
+        &self.visible
+    }
+}
+
+/// Replaces the map's visible tiles with `visible`, and remembers them as revealed.
+pub fn update_visibility(map: &mut Map, visible: &[Point]) {
+    map.clear_visible();
+    for point in visible {
+        map.set_visible(*point);
+        map.set_revealed(*point);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FieldOfView;
+    use crate::geom::Point;
+    use crate::map::{Map, TileType};
+    use crate::tiles::TileDefinitions;
+
+    fn tiles() -> TileDefinitions {
+        TileDefinitions::load_from_path("static/data/tiles.json").unwrap()
+    }
+
+    fn open_map(size: (i32, i32)) -> Map {
+        let mut map = Map::new(size, 0);
+        for x in 1..size.0 - 1 {
+            for y in 1..size.1 - 1 {
+                map.set_type(Point::new(x, y), TileType::Floor);
+            }
+        }
+        map.refresh_blocked(&tiles());
+        map
+    }
+
+    #[test]
+    fn test_walls_cast_shadows() {
+        let mut map = open_map((11, 11));
+        map.set_type(Point::new(5, 3), TileType::Wall);
+        map.refresh_blocked(&tiles());
+        let mut fov = FieldOfView::default();
+        let visible = fov.visible(Point::new(5, 5), 10, &map);
+        assert!(visible.contains(&Point::new(5, 5)));
+        assert!(visible.contains(&Point::new(5, 3)));
+        assert!(!visible.contains(&Point::new(5, 2)));
+        assert!(visible.contains(&Point::new(0, 5)));
+        assert!(visible.contains(&Point::new(9, 9)));
+    }
+
+    #[test]
+    fn test_sight_is_symmetric() {
+        let mut map = open_map((12, 10));
+        for (x, y) in &[(4, 3), (7, 5), (8, 7), (10, 4), (5, 7), (9, 2)] {
+            map.set_type(Point::new(*x, *y), TileType::Wall);
+        }
+        map.refresh_blocked(&tiles());
+        let floors = (0..map.tiles.len())
+            .map(|index| map.index_to_point(index))
+            .filter(|point| map.get_type(*point) == TileType::Floor)
+            .collect::<Vec<_>>();
+        let (mut fov_a, mut fov_b) = (FieldOfView::default(), FieldOfView::default());
+        for a in floors.iter() {
+            let from_a = fov_a.visible(*a, 20, &map);
+            for b in floors.iter() {
+                if from_a.contains(b) {
+                    assert!(
+                        fov_b.visible(*b, 20, &map).contains(a),
+                        "{:?} sees {:?} but not the other way round",
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_tiles_are_seen_once() {
+        let mut fov = FieldOfView::default();
+        for size in &[(11, 11), (7, 9)] {
+            let map = open_map(*size);
+            let mut visible = fov.visible(Point::new(3, 3), 10, &map).to_vec();
+            let count = visible.len();
+            visible.sort_by_key(|point| (point.y, point.x));
+            visible.dedup();
+            assert_eq!(visible.len(), count);
+            assert_eq!(count, map.tiles.len());
+        }
     }
 }
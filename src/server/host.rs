@@ -0,0 +1,166 @@
+use crate::color::Color;
+use crate::map::Map;
+use crate::message::Message;
+use crate::network::protocol::{Request, ServerMessage};
+use crate::network::ServerTransport;
+use crate::server::replay::CHECKPOINT_INTERVAL;
+use crate::server::replication::Replicator;
+use crate::server::save::SaveGame;
+use crate::server::server::Server;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+/// Owns a `Server` and talks to a single client over some transport. Requests are
+/// queued until it's the player's turn, then handed over one at a time and answered
+/// once the server has carried them out. After every tick the client is sent whatever
+/// changed in the world.
+///
+/// Save and Load aren't game actions, so they bypass the turn queue: they're carried
+/// out as soon as they arrive and are never written to a recording. Loading drops any
+/// queued requests, stops the recording and sends the client a fresh snapshot.
+pub struct ServerHost {
+    server: Server,
+    transport: Box<dyn ServerTransport>,
+    pending: VecDeque<Request>,
+    in_flight: Option<Request>,
+    replicator: Replicator,
+    record_path: Option<PathBuf>,
+    flushed_commands: usize,
+}
+
+impl ServerHost {
+    pub fn new(server: Server, transport: Box<dyn ServerTransport>) -> Self {
+        ServerHost {
+            server,
+            transport,
+            pending: VecDeque::new(),
+            in_flight: None,
+            replicator: Replicator::new(),
+            record_path: None,
+            flushed_commands: 0,
+        }
+    }
+
+    /// Records the session to `path`. The file is rewritten at every checkpoint that
+    /// follows a new command, so it survives the game crashing.
+    pub fn record_to(&mut self, path: impl Into<PathBuf>) {
+        self.server.start_recording();
+        self.record_path = Some(path.into());
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    pub fn server_mut(&mut self) -> &mut Server {
+        &mut self.server
+    }
+
+    fn send_snapshot(&mut self) {
+        let seed = self.server.seed();
+        let map = self.server.resources.get::<Map>().unwrap();
+        let snapshot = self.replicator.snapshot(seed, &self.server.world, &map);
+        std::mem::drop(map);
+        self.transport.send(ServerMessage::Snapshot(snapshot));
+    }
+
+    fn report(&mut self, text: String, color: Color) {
+        self.transport.send(ServerMessage::Event(Message::GameEvent(
+            text,
+            Some(color),
+            None,
+        )));
+    }
+
+    // Saving and loading aren't game actions, so they skip the turn queue.
+    fn handle_session_request(&mut self, request: Request) {
+        let result = match request {
+            Request::Save => self.server.save().store().map(|_| "Game saved"),
+            Request::Load => SaveGame::restore().map(|save| {
+                self.server.load(save);
+                self.pending.clear();
+                self.in_flight = None;
+                self.send_snapshot();
+                "Game loaded"
+            }),
+            _ => unreachable!(),
+        };
+        let success = result.is_ok();
+        match result {
+            Ok(text) => self.report(text.to_string(), Color::GREEN),
+            Err(err) => self.report(format!("{:?}", err), Color::RED),
+        }
+        self.transport
+            .send(ServerMessage::Response { request, success });
+    }
+
+    fn flush_recording(&mut self) {
+        let (path, recording) = match (&self.record_path, self.server.recording()) {
+            (Some(path), Some(recording)) => (path, recording),
+            _ => return,
+        };
+        if self.server.ticks() % CHECKPOINT_INTERVAL != 0
+            || recording.commands.len() == self.flushed_commands
+        {
+            return;
+        }
+        self.flushed_commands = recording.commands.len();
+        let result = recording
+            .to_json()
+            .and_then(|raw| std::fs::write(path, raw).map_err(From::from));
+        if let Err(err) = result {
+            self.report(format!("{:?}", err), Color::RED);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.transport.new_connection() {
+            self.pending.clear();
+            self.in_flight = None;
+            self.send_snapshot();
+        }
+
+        for request in self.transport.receive() {
+            match request {
+                Request::Save | Request::Load => self.handle_session_request(request),
+                request => self.pending.push_back(request),
+            }
+        }
+        while self.in_flight.is_none() && !self.pending.is_empty() && self.server.is_player_turn() {
+            let request = self.pending.pop_front().unwrap();
+            if self.server.handle_request(request) {
+                self.in_flight = Some(request);
+            } else {
+                self.transport.send(ServerMessage::Response {
+                    request,
+                    success: false,
+                });
+            }
+        }
+
+        self.server.tick();
+        let results = self.server.action_results();
+        if !results.is_empty() {
+            let player = self.server.get_player();
+            for result in results.into_iter().filter(|result| result.actor == player) {
+                if let Some(request) = self.in_flight.take() {
+                    self.transport.send(ServerMessage::Response {
+                        request,
+                        success: result.success,
+                    });
+                }
+            }
+        }
+        self.flush_recording();
+        for message in self.server.messages() {
+            self.transport.send(ServerMessage::Event(message));
+        }
+
+        let map = self.server.resources.get::<Map>().unwrap();
+        let delta = self.replicator.delta(&self.server.world, &map);
+        std::mem::drop(map);
+        if let Some(delta) = delta {
+            self.transport.send(ServerMessage::Delta(delta));
+        }
+    }
+}
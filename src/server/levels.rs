@@ -0,0 +1,135 @@
+use crate::geom::Vector;
+use crate::map::{Map, TileType};
+use crate::server::map_builders::area_starting_points::{AreaStartingPosition, XStart, YStart};
+use crate::server::map_builders::cull_unreachable::CullUnreachable;
+use crate::server::map_builders::distant_exit::DistantExit;
+use crate::server::map_builders::factories::{
+    bsp_builder, cave_builder, drunk_builder, random_builder, wfc_builder,
+};
+use crate::server::map_builders::prefab::{PrefabBuilder, Prefabs};
+use crate::server::map_builders::spawner::{AreaSpawner, SpawnTables};
+use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+use crate::server::save::SavedEntity;
+use crate::tiles::TileDefinitions;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Size of every level below the shop.
+pub const LEVEL_SIZE: (i32, i32) = (60, 40);
+
+/// Set by the action system when the player takes the stairs. The server moves them to
+/// the new depth once the tick is over.
+#[derive(Default)]
+pub struct LevelTransition {
+    pub depth: Option<i32>,
+}
+
+/// A level the player isn't on right now, kept exactly as they left it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ParkedLevel {
+    pub map: Map,
+    pub entities: Vec<SavedEntity>,
+}
+
+/// Every level the player has visited apart from the current one, by depth.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct LevelManager {
+    parked: BTreeMap<i32, ParkedLevel>,
+}
+
+impl LevelManager {
+    pub fn new() -> Self {
+        LevelManager::default()
+    }
+
+    pub fn park(&mut self, map: Map, entities: Vec<SavedEntity>) {
+        self.parked.insert(map.depth, ParkedLevel { map, entities });
+    }
+
+    /// Takes a previously visited level back out, if there is one.
+    pub fn unpark(&mut self, depth: i32) -> Option<ParkedLevel> {
+        self.parked.remove(&depth)
+    }
+}
+
+/// Builds a fresh level for `depth`. Anything the player couldn't walk to is filled in,
+/// then they arrive on stairs up near a randomly chosen side, corner or the middle of the
+/// map, with stairs down as far away from them as the layout allows. Vault prefabs are
+/// stamped in wherever there's room for them, and the rest is stocked from the
+/// `stock_room` spawn table. A layout with nowhere to start is thrown away for plain
+/// rooms.
+pub fn generate(
+    depth: i32,
+    tiles: &TileDefinitions,
+    prefabs: &Prefabs,
+    spawn_tables: &SpawnTables,
+    rng: &mut RandomNumberGenerator,
+) -> BuiltMap {
+    let size: Vector = LEVEL_SIZE.into();
+    let mut built_map = match rng.gen_range(0, 5) {
+        0 => random_builder(size, depth, false, rng),
+        1 => drunk_builder(size, depth, false, rng),
+        2 => bsp_builder(size, depth, false, rng),
+        3 => cave_builder(size, depth, false, rng),
+        _ => wfc_builder(size, depth, false, rng),
+    };
+    // Caves can come out without a single floor tile to stand on. Plain rooms always
+    // have one, so fall back on those.
+    if built_map.starting_position.is_none() {
+        built_map = random_builder(size, depth, false, rng);
+    }
+    CullUnreachable {
+        tiles: tiles.clone(),
+    }
+    .mutate(rng, &mut built_map);
+    let x = [XStart::Left, XStart::Center, XStart::Right][rng.gen_range(0, 3)];
+    let y = [YStart::Top, YStart::Center, YStart::Bottom][rng.gen_range(0, 3)];
+    AreaStartingPosition { x, y }.mutate(rng, &mut built_map);
+    PrefabBuilder {
+        prefabs: prefabs.tagged("vault"),
+    }
+    .mutate(rng, &mut built_map);
+    if let Some(start) = built_map.starting_position {
+        built_map.map.set_type(start, TileType::UpStairs);
+    }
+    DistantExit {
+        tiles: tiles.clone(),
+    }
+    .mutate(rng, &mut built_map);
+    AreaSpawner {
+        table: spawn_tables.get("stock_room").clone(),
+    }
+    .mutate(rng, &mut built_map);
+    built_map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate;
+    use crate::map::TileType;
+    use crate::server::map_builders::prefab::Prefabs;
+    use crate::server::map_builders::spawner::SpawnTables;
+    use crate::server::rng::RandomNumberGenerator;
+    use crate::tiles::TileDefinitions;
+
+    #[test]
+    fn test_generated_levels_have_stairs() {
+        let tiles = TileDefinitions::load_from_path("static/data/tiles.json").unwrap();
+        let prefabs = Prefabs::load_from_path("static/data/prefabs.txt").unwrap();
+        let spawn_tables = SpawnTables::load_from_path("static/data/spawn_tables.json").unwrap();
+        for seed in 0..8 {
+            let built_map = generate(
+                1,
+                &tiles,
+                &prefabs,
+                &spawn_tables,
+                &mut RandomNumberGenerator::seeded(seed),
+            );
+            assert_eq!(built_map.map.depth, 1);
+            assert!(built_map.map.find(TileType::UpStairs).is_some());
+            assert!(built_map.map.find(TileType::DownStairs).is_some());
+        }
+    }
+}
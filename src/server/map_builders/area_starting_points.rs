@@ -0,0 +1,81 @@
+use crate::geom::Point;
+use crate::map::TileType;
+use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum XStart {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum YStart {
+    Top,
+    Center,
+    Bottom,
+}
+
+/// Moves the starting position to the floor tile closest to one side, corner or the
+/// middle of the map.
+pub struct AreaStartingPosition {
+    pub x: XStart,
+    pub y: YStart,
+}
+
+// https://bfnightly.bracketproductions.com/rustbook/chapter_41.html
+impl MetaMapBuilder for AreaStartingPosition {
+    fn mutate(&mut self, _: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        let map = &build_data.map;
+        let (width, height) = map.size.to_tuple();
+        let target = Point::new(
+            match self.x {
+                XStart::Left => 1,
+                XStart::Center => width / 2,
+                XStart::Right => width - 2,
+            },
+            match self.y {
+                YStart::Top => 1,
+                YStart::Center => height / 2,
+                YStart::Bottom => height - 2,
+            },
+        );
+        let nearest = (0..map.tiles.len())
+            .filter(|index| map.tiles[*index] == TileType::Floor)
+            .map(|index| map.index_to_point(index))
+            .min_by_key(|point| {
+                let (dx, dy) = (point.x - target.x, point.y - target.y);
+                dx * dx + dy * dy
+            });
+        if nearest.is_some() {
+            build_data.starting_position = nearest;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AreaStartingPosition, XStart, YStart};
+    use crate::geom::{Point, Rect};
+    use crate::server::map_builders::shop_builder::create_room;
+    use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+    use crate::server::rng::RandomNumberGenerator;
+
+    #[test]
+    fn test_starts_nearest_the_chosen_area() {
+        let mut build_data = BuiltMap::new((20, 10).into(), 1);
+        create_room(
+            &mut build_data.map,
+            &Rect::new((4, 2).into(), (10, 5).into()),
+        );
+        let mut rng = RandomNumberGenerator::seeded(0);
+        let mut start = |x, y| {
+            AreaStartingPosition { x, y }.mutate(&mut rng, &mut build_data);
+            build_data.starting_position.unwrap()
+        };
+        assert_eq!(start(XStart::Left, YStart::Top), Point::new(4, 2));
+        assert_eq!(start(XStart::Right, YStart::Bottom), Point::new(13, 6));
+        assert_eq!(start(XStart::Center, YStart::Center), Point::new(10, 5));
+    }
+}
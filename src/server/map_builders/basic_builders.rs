@@ -1,14 +1,14 @@
 use crate::geom::Rect;
 use crate::map::{Map, TileType};
 use crate::server::map_builders::{BaseMapBuilder, BuiltMap};
-use rand::prelude::ThreadRng;
+use crate::server::rng::RandomNumberGenerator;
 use rand::Rng;
 use std::cmp::{max, min};
 
 pub struct RoomMapBuilder;
 
 impl BaseMapBuilder for RoomMapBuilder {
-    fn build(&mut self, _: &mut ThreadRng, build_data: &mut BuiltMap) {
+    fn build(&mut self, _: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
         let size: (i32, i32) = build_data.map.size.to_tuple();
         let map = &mut build_data.map;
         create_room(
@@ -23,13 +23,13 @@ impl BaseMapBuilder for RoomMapBuilder {
 pub struct SimpleMapBuilder;
 
 impl BaseMapBuilder for SimpleMapBuilder {
-    fn build(&mut self, rng: &mut ThreadRng, build_data: &mut BuiltMap) {
+    fn build(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
         SimpleMapBuilder::rooms_and_corridors(rng, build_data);
     }
 }
 
 impl SimpleMapBuilder {
-    pub fn rooms_and_corridors(rng: &mut ThreadRng, build_data: &mut BuiltMap) {
+    pub fn rooms_and_corridors(rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
         const MAX_ROOMS: i32 = 30;
         const MIN_SIZE: i32 = 6;
         const MAX_SIZE: i32 = 10;
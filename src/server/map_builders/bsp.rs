@@ -0,0 +1,154 @@
+use crate::geom::Rect;
+use crate::server::map_builders::basic_builders::{create_room, dig_horizontal, dig_vertical};
+use crate::server::map_builders::{BaseMapBuilder, BuiltMap};
+use crate::server::rng::RandomNumberGenerator;
+use rand::Rng;
+
+const MIN_ROOM_SIZE: i32 = 4;
+
+/// Splits the map in two over and over, puts a room in each of the smallest pieces and
+/// joins every pair of halves back up with a corridor, so every room can be reached.
+pub struct BspMapBuilder {
+    /// Pieces are never split smaller than this along either side.
+    pub min_leaf_size: i32,
+}
+
+// http://www.roguebasin.com/index.php?title=Basic_BSP_Dungeon_generation
+impl BaseMapBuilder for BspMapBuilder {
+    fn build(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        let size = build_data.map.size;
+        let area = Rect::new((1, 1).into(), (size.x - 2, size.y - 2).into());
+        let mut rooms = vec![];
+        self.split(rng, build_data, area, &mut rooms);
+
+        // Maps too small for even one room are left solid, with nowhere to start.
+        build_data.starting_position = rooms.first().map(|room| room.center());
+        build_data.rooms = Some(rooms);
+    }
+}
+
+impl BspMapBuilder {
+    /// Fills `area` with rooms, returning the index of one of them to connect to, if
+    /// there was room for any.
+    fn split(
+        &self,
+        rng: &mut RandomNumberGenerator,
+        build_data: &mut BuiltMap,
+        area: Rect,
+        rooms: &mut Vec<Rect>,
+    ) -> Option<usize> {
+        let (width, height) = (area.size.width, area.size.height);
+        let can_split_x = width >= self.min_leaf_size * 2;
+        let can_split_y = height >= self.min_leaf_size * 2;
+        if !can_split_x && !can_split_y {
+            return self.place_room(rng, build_data, area, rooms);
+        }
+
+        // Cut across the longer side, unless the shape gives no reason to prefer one.
+        let split_x = match (can_split_x, can_split_y) {
+            (true, false) => true,
+            (false, true) => false,
+            _ if width * 4 > height * 5 => true,
+            _ if height * 4 > width * 5 => false,
+            _ => rng.gen_bool(0.5),
+        };
+        let (first, second) = if split_x {
+            let at = rng.gen_range(self.min_leaf_size, width - self.min_leaf_size + 1);
+            (
+                Rect::new(area.origin, (at, height).into()),
+                Rect::new(
+                    (area.origin.x + at, area.origin.y).into(),
+                    (width - at, height).into(),
+                ),
+            )
+        } else {
+            let at = rng.gen_range(self.min_leaf_size, height - self.min_leaf_size + 1);
+            (
+                Rect::new(area.origin, (width, at).into()),
+                Rect::new(
+                    (area.origin.x, area.origin.y + at).into(),
+                    (width, height - at).into(),
+                ),
+            )
+        };
+
+        let (first, second) = match (
+            self.split(rng, build_data, first, rooms),
+            self.split(rng, build_data, second, rooms),
+        ) {
+            (Some(first), Some(second)) => (first, second),
+            // With a room on at most one side there's nothing to join up.
+            (first, second) => return first.or(second),
+        };
+        let (from, to) = (rooms[first].center(), rooms[second].center());
+        if rng.gen_bool(0.5) {
+            dig_horizontal(&mut build_data.map, from.x, to.x, from.y);
+            dig_vertical(&mut build_data.map, from.y, to.y, to.x);
+        } else {
+            dig_vertical(&mut build_data.map, from.y, to.y, from.x);
+            dig_horizontal(&mut build_data.map, from.x, to.x, to.y);
+        }
+        build_data.take_snapshot();
+
+        if rng.gen_bool(0.5) {
+            Some(first)
+        } else {
+            Some(second)
+        }
+    }
+
+    /// Puts a room somewhere inside `leaf`, leaving at least a tile of wall around it.
+    /// Leaves too small to hold a room of `MIN_ROOM_SIZE` are left as solid wall.
+    fn place_room(
+        &self,
+        rng: &mut RandomNumberGenerator,
+        build_data: &mut BuiltMap,
+        leaf: Rect,
+        rooms: &mut Vec<Rect>,
+    ) -> Option<usize> {
+        let (width, height) = (leaf.size.width, leaf.size.height);
+        if width < MIN_ROOM_SIZE + 2 || height < MIN_ROOM_SIZE + 2 {
+            return None;
+        }
+        let room_width = rng.gen_range(MIN_ROOM_SIZE, width - 1);
+        let room_height = rng.gen_range(MIN_ROOM_SIZE, height - 1);
+        let x = leaf.origin.x + rng.gen_range(1, width - room_width);
+        let y = leaf.origin.y + rng.gen_range(1, height - room_height);
+        let room = Rect::new((x, y).into(), (room_width, room_height).into());
+        create_room(&mut build_data.map, &room);
+        build_data.take_snapshot();
+        rooms.push(room);
+        Some(rooms.len() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::TileType;
+    use crate::server::map_builders::factories::bsp_builder;
+    use crate::server::rng::RandomNumberGenerator;
+
+    #[test]
+    fn test_small_maps() {
+        for size in &[(0, 0), (2, 2), (3, 5), (7, 7), (8, 8), (9, 20), (17, 8)] {
+            for seed in 0..8 {
+                let mut rng = RandomNumberGenerator::seeded(seed);
+                let built = bsp_builder((*size).into(), 1, false, &mut rng);
+                let rooms = built.rooms.unwrap();
+                if size.0 < 8 || size.1 < 8 {
+                    assert!(rooms.is_empty(), "{:?} has no space for a room", size);
+                    assert_eq!(built.starting_position, None);
+                    continue;
+                }
+                assert!(!rooms.is_empty(), "no rooms in {:?}", size);
+                for room in rooms.iter() {
+                    assert!(room.size.width >= 4 && room.size.height >= 4);
+                    assert!(room.min_x() >= 1 && room.min_y() >= 1);
+                    assert!(room.max_x() < size.0 && room.max_y() < size.1);
+                }
+                let start = built.starting_position.unwrap();
+                assert_eq!(built.map.get_type(start), TileType::Floor);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::geom::Point;
+use crate::map::TileType;
+use crate::server::map_builders::area_starting_points::{AreaStartingPosition, XStart, YStart};
+use crate::server::map_builders::{BaseMapBuilder, BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+use rand::Rng;
+
+/// Grows caves by scattering walls at random and then smoothing them out, a few rounds
+/// at a time, like Conway's game of life.
+pub struct CellularAutomataBuilder {
+    /// Share of the map that starts out as wall.
+    pub fill_ratio: f32,
+    pub iterations: u32,
+    /// Floor turns into wall when it has at least this many wall neighbours.
+    pub birth_limit: usize,
+    /// Wall stays wall while it has at least this many wall neighbours.
+    pub survival_limit: usize,
+}
+
+// https://bfnightly.bracketproductions.com/rustbook/chapter_27.html
+impl BaseMapBuilder for CellularAutomataBuilder {
+    fn build(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        let (width, height) = build_data.map.size.to_tuple();
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                let tile = if rng.gen::<f32>() < self.fill_ratio {
+                    TileType::Wall
+                } else {
+                    TileType::Floor
+                };
+                build_data.map.set_type((x, y).into(), tile);
+            }
+        }
+        build_data.take_snapshot();
+
+        for _ in 0..self.iterations {
+            let mut tiles = build_data.map.tiles.clone();
+            for y in 1..height - 1 {
+                for x in 1..width - 1 {
+                    let walls = self.wall_neighbours(build_data, (x, y).into());
+                    let index = build_data.map.coord_to_index(x, y);
+                    let is_wall = build_data.map.tiles[index] == TileType::Wall;
+                    tiles[index] = if (is_wall && walls >= self.survival_limit)
+                        || (!is_wall && walls >= self.birth_limit)
+                    {
+                        TileType::Wall
+                    } else {
+                        TileType::Floor
+                    };
+                }
+            }
+            build_data.map.tiles = tiles;
+            build_data.take_snapshot();
+        }
+
+        // Caves rarely leave the middle of the map open, so start on the closest floor to it.
+        AreaStartingPosition {
+            x: XStart::Center,
+            y: YStart::Center,
+        }
+        .mutate(rng, build_data);
+    }
+}
+
+impl CellularAutomataBuilder {
+    fn wall_neighbours(&self, build_data: &BuiltMap, point: Point) -> usize {
+        let map = &build_data.map;
+        let mut walls = 0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let neighbour = Point::new(point.x + dx, point.y + dy);
+                if !map.in_bounds(neighbour) || map.get_type(neighbour) == TileType::Wall {
+                    walls += 1;
+                }
+            }
+        }
+        walls
+    }
+}
@@ -0,0 +1,66 @@
+use crate::dijkstra_map::DijkstraMap;
+use crate::map::TileType;
+use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+use crate::tiles::TileDefinitions;
+
+/// Walls over every open tile that can't be walked to from the starting position, so
+/// nothing ends up stranded in a pocket the player can never reach.
+pub struct CullUnreachable {
+    pub tiles: TileDefinitions,
+}
+
+// https://bfnightly.bracketproductions.com/rustbook/chapter_41.html
+impl MetaMapBuilder for CullUnreachable {
+    fn mutate(&mut self, _: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        // With nowhere to start from there's nothing to measure from, so leave the map be.
+        let start = match build_data.starting_position {
+            Some(start) => start,
+            None => return,
+        };
+        let map = &mut build_data.map;
+        map.refresh_blocked(&self.tiles);
+        let dijkstra_map = DijkstraMap::new(map, &[(start, 0)], i32::max_value());
+        for index in 0..map.tiles.len() {
+            let point = map.index_to_point(index);
+            if !map.blocked[index] && !dijkstra_map.is_reachable(point) {
+                map.tiles[index] = TileType::Wall;
+            }
+        }
+        map.refresh_blocked(&self.tiles);
+        build_data.take_snapshot();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CullUnreachable;
+    use crate::geom::{Point, Rect};
+    use crate::map::TileType;
+    use crate::server::map_builders::shop_builder::create_room;
+    use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+    use crate::server::rng::RandomNumberGenerator;
+    use crate::tiles::TileDefinitions;
+
+    #[test]
+    fn test_culls_disconnected_floor() {
+        let mut build_data = BuiltMap::new((12, 6).into(), 1);
+        create_room(
+            &mut build_data.map,
+            &Rect::new((1, 1).into(), (4, 4).into()),
+        );
+        create_room(
+            &mut build_data.map,
+            &Rect::new((7, 1).into(), (4, 4).into()),
+        );
+        build_data.starting_position = Some(Point::new(2, 2));
+        CullUnreachable {
+            tiles: TileDefinitions::load_from_path("static/data/tiles.json").unwrap(),
+        }
+        .mutate(&mut RandomNumberGenerator::seeded(0), &mut build_data);
+
+        assert_eq!(build_data.map.get_type(Point::new(4, 4)), TileType::Floor);
+        assert_eq!(build_data.map.get_type(Point::new(7, 1)), TileType::Wall);
+        assert_eq!(build_data.map.get_type(Point::new(10, 4)), TileType::Wall);
+    }
+}
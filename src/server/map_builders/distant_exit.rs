@@ -0,0 +1,85 @@
+use crate::dijkstra_map::DijkstraMap;
+use crate::map::TileType;
+use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+use crate::tiles::TileDefinitions;
+
+/// Puts the stairs down on the tile furthest from the starting position, as long as
+/// there's anywhere further to go than the start itself.
+pub struct DistantExit {
+    pub tiles: TileDefinitions,
+}
+
+// https://bfnightly.bracketproductions.com/rustbook/chapter_41.html
+impl MetaMapBuilder for DistantExit {
+    fn mutate(&mut self, _: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        // With nowhere to start from there's nothing to measure from, so leave the map be.
+        let start = match build_data.starting_position {
+            Some(start) => start,
+            None => return,
+        };
+        let map = &mut build_data.map;
+        map.refresh_blocked(&self.tiles);
+        let exit = DijkstraMap::new(map, &[(start, 0)], i32::max_value()).farthest();
+        if let Some(exit) = exit.filter(|exit| *exit != start) {
+            map.set_type(exit, TileType::DownStairs);
+            map.refresh_blocked(&self.tiles);
+        }
+        build_data.take_snapshot();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DistantExit;
+    use crate::geom::{Point, Rect};
+    use crate::map::TileType;
+    use crate::server::map_builders::shop_builder::create_room;
+    use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+    use crate::server::rng::RandomNumberGenerator;
+    use crate::tiles::TileDefinitions;
+
+    fn place_exit(build_data: &mut BuiltMap) {
+        DistantExit {
+            tiles: TileDefinitions::load_from_path("static/data/tiles.json").unwrap(),
+        }
+        .mutate(&mut RandomNumberGenerator::seeded(0), build_data);
+    }
+
+    #[test]
+    fn test_exit_is_farthest_from_the_start() {
+        // An L of corridors, starting at the end of the top one.
+        let mut build_data = BuiltMap::new((12, 10).into(), 1);
+        create_room(
+            &mut build_data.map,
+            &Rect::new((1, 1).into(), (8, 1).into()),
+        );
+        create_room(
+            &mut build_data.map,
+            &Rect::new((1, 1).into(), (1, 6).into()),
+        );
+        build_data.starting_position = Some(Point::new(8, 1));
+        place_exit(&mut build_data);
+
+        assert_eq!(
+            build_data.map.find(TileType::DownStairs),
+            Some(Point::new(1, 6))
+        );
+    }
+
+    #[test]
+    fn test_no_exit_without_anywhere_to_go() {
+        let mut build_data = BuiltMap::new((5, 5).into(), 1);
+        create_room(
+            &mut build_data.map,
+            &Rect::new((2, 2).into(), (1, 1).into()),
+        );
+        let tiles = build_data.map.tiles.clone();
+        place_exit(&mut build_data);
+        assert_eq!(build_data.map.tiles, tiles);
+
+        build_data.starting_position = Some(Point::new(2, 2));
+        place_exit(&mut build_data);
+        assert_eq!(build_data.map.tiles, tiles);
+    }
+}
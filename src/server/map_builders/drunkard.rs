@@ -1,7 +1,7 @@
 use crate::geom::Point;
 use crate::map::TileType;
 use crate::server::map_builders::{BaseMapBuilder, BuiltMap};
-use rand::prelude::ThreadRng;
+use crate::server::rng::RandomNumberGenerator;
 use rand::Rng;
 
 pub struct DrunkardsWalkBuilder {
@@ -11,7 +11,7 @@ pub struct DrunkardsWalkBuilder {
 }
 // https://bfnightly.bracketproductions.com/rustbook/chapter_36.html
 impl BaseMapBuilder for DrunkardsWalkBuilder {
-    fn build(&mut self, rng: &mut ThreadRng, build_data: &mut BuiltMap) {
+    fn build(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
         let starting_position: Point = ((build_data.map.size) / 2).to_tuple().into();
         let total_tiles = build_data.map.size.x * build_data.map.size.y;
         let desired_floor = (self.floor_percent * total_tiles as f32) as usize;
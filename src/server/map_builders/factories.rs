@@ -1,18 +1,40 @@
 use crate::geom::Vector;
 use crate::server::map_builders::basic_builders::SimpleMapBuilder;
+use crate::server::map_builders::bsp::BspMapBuilder;
+use crate::server::map_builders::cellular_automata::CellularAutomataBuilder;
 use crate::server::map_builders::drunkard::DrunkardsWalkBuilder;
+use crate::server::map_builders::prefab::{PrefabBuilder, Prefabs};
+use crate::server::map_builders::waveform_collapse::{Sample, WaveformCollapseBuilder};
 use crate::server::map_builders::{BuiltMap, MapBuilder};
-use rand::prelude::ThreadRng;
+use crate::server::rng::RandomNumberGenerator;
 use super::shop_builder::ShopBuilder;
 
-pub fn random_builder(size: Vector, depth: i32, rng: &mut ThreadRng) -> BuiltMap {
-    MapBuilder::new(size, depth, SimpleMapBuilder)
-        //    .keep_history()
-        .build(rng)
+/// Runs the builder, snapshotting each step into the map's `history` if asked to.
+fn build(builder: MapBuilder, keep_history: bool, rng: &mut RandomNumberGenerator) -> BuiltMap {
+    if keep_history {
+        builder.keep_history().build(rng)
+    } else {
+        builder.build(rng)
+    }
 }
 
-pub fn drunk_builder(size: Vector, depth: i32, rng: &mut ThreadRng) -> BuiltMap {
-    MapBuilder::new(
+pub fn random_builder(
+    size: Vector,
+    depth: i32,
+    keep_history: bool,
+    rng: &mut RandomNumberGenerator,
+) -> BuiltMap {
+    let builder = MapBuilder::new(size, depth, SimpleMapBuilder);
+    build(builder, keep_history, rng)
+}
+
+pub fn drunk_builder(
+    size: Vector,
+    depth: i32,
+    keep_history: bool,
+    rng: &mut RandomNumberGenerator,
+) -> BuiltMap {
+    let builder = MapBuilder::new(
         size,
         depth,
         DrunkardsWalkBuilder {
@@ -20,11 +42,179 @@ pub fn drunk_builder(size: Vector, depth: i32, rng: &mut ThreadRng) -> BuiltMap
             floor_percent: 0.6,
             brush_size: 1,
         },
+    );
+    build(builder, keep_history, rng)
+}
+
+pub fn bsp_builder(
+    size: Vector,
+    depth: i32,
+    keep_history: bool,
+    rng: &mut RandomNumberGenerator,
+) -> BuiltMap {
+    let builder = MapBuilder::new(size, depth, BspMapBuilder { min_leaf_size: 8 });
+    build(builder, keep_history, rng)
+}
+
+pub fn cave_builder(
+    size: Vector,
+    depth: i32,
+    keep_history: bool,
+    rng: &mut RandomNumberGenerator,
+) -> BuiltMap {
+    let builder = MapBuilder::new(
+        size,
+        depth,
+        CellularAutomataBuilder {
+            fill_ratio: 0.45,
+            iterations: 15,
+            birth_limit: 5,
+            survival_limit: 4,
+        },
+    );
+    build(builder, keep_history, rng)
+}
+
+/// Caves reshuffled by wave function collapse into something similar but less blobby.
+pub fn wfc_builder(
+    size: Vector,
+    depth: i32,
+    keep_history: bool,
+    rng: &mut RandomNumberGenerator,
+) -> BuiltMap {
+    let builder = MapBuilder::new(
+        size,
+        depth,
+        CellularAutomataBuilder {
+            fill_ratio: 0.45,
+            iterations: 15,
+            birth_limit: 5,
+            survival_limit: 4,
+        },
     )
-    // .keep_history()
-    .build(rng)
+    .with(WaveformCollapseBuilder {
+        pattern_size: 3,
+        sample: Sample::Map,
+    });
+    build(builder, keep_history, rng)
+}
+
+pub fn shop_builder(
+    size: Vector,
+    prefabs: &Prefabs,
+    keep_history: bool,
+    rng: &mut RandomNumberGenerator,
+) -> BuiltMap {
+    let builder = MapBuilder::new(size, 0, ShopBuilder).with(PrefabBuilder {
+        prefabs: prefabs.tagged("shop"),
+    });
+    build(builder, keep_history, rng)
 }
 
-pub fn shop_builder(size: Vector, rng: &mut ThreadRng) -> BuiltMap {
-    MapBuilder::new(size, 0, ShopBuilder).build(rng)
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::{bsp_builder, cave_builder, drunk_builder, random_builder, shop_builder};
+    use crate::dijkstra_map::DijkstraMap;
+    use crate::map::TileType;
+    use crate::server::map_builders::prefab::Prefabs;
+    use crate::server::rng::RandomNumberGenerator;
+
+    #[test]
+    fn test_same_seed_same_layout() {
+        for seed in &[0, 7, 0xdead_beef] {
+            // Each builder run consumes its own generator so the comparison is like for like.
+            let first = random_builder(
+                (60, 40).into(),
+                1,
+                false,
+                &mut RandomNumberGenerator::seeded(*seed),
+            );
+            let second = random_builder(
+                (60, 40).into(),
+                1,
+                false,
+                &mut RandomNumberGenerator::seeded(*seed),
+            );
+            assert_eq!(first.map.tiles, second.map.tiles);
+            assert_eq!(first.starting_position, second.starting_position);
+
+            let first = drunk_builder(
+                (60, 40).into(),
+                1,
+                false,
+                &mut RandomNumberGenerator::seeded(*seed),
+            );
+            let second = drunk_builder(
+                (60, 40).into(),
+                1,
+                false,
+                &mut RandomNumberGenerator::seeded(*seed),
+            );
+            assert_eq!(first.map.tiles, second.map.tiles);
+        }
+    }
+
+    #[test]
+    fn test_caves_start_on_floor() {
+        for seed in 0..4 {
+            let built_map = cave_builder(
+                (60, 40).into(),
+                1,
+                false,
+                &mut RandomNumberGenerator::seeded(seed),
+            );
+            let start = built_map
+                .starting_position
+                .expect("caves should have floor");
+            assert_eq!(built_map.map.get_type(start), TileType::Floor);
+        }
+    }
+
+    #[test]
+    fn test_bsp_rooms_are_connected() {
+        for seed in 0..4 {
+            let built_map = bsp_builder(
+                (60, 40).into(),
+                1,
+                false,
+                &mut RandomNumberGenerator::seeded(seed),
+            );
+            let start = built_map.starting_position.unwrap();
+            let dijkstra_map = DijkstraMap::new(&built_map.map, &[(start, 0)], i32::max_value());
+            let rooms = built_map.rooms.unwrap();
+            assert!(rooms.len() > 1);
+            for room in rooms {
+                assert!(dijkstra_map.is_reachable(room.center()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_shop_history_ends_with_the_finished_shop() {
+        let prefabs = Prefabs::load_from_path("static/data/prefabs.txt").unwrap();
+        let mut rng = RandomNumberGenerator::seeded(0);
+        let built_map = shop_builder((20, 20).into(), &prefabs, true, &mut rng);
+        assert!(built_map.history.len() > 1);
+        assert_eq!(built_map.history.last().unwrap().tiles, built_map.map.tiles);
+
+        let built_map = shop_builder((20, 20).into(), &prefabs, false, &mut rng);
+        assert!(built_map.history.is_empty());
+    }
+
+    #[test]
+    fn test_different_seeds_differ() {
+        let first = drunk_builder(
+            (60, 40).into(),
+            1,
+            false,
+            &mut RandomNumberGenerator::seeded(1),
+        );
+        let second = drunk_builder(
+            (60, 40).into(),
+            1,
+            false,
+            &mut RandomNumberGenerator::seeded(2),
+        );
+        assert_ne!(first.map.tiles, second.map.tiles);
+    }
+}
@@ -1,19 +1,28 @@
 use crate::geom::{Point, Rect, Vector};
 use crate::map::Map;
-use rand::prelude::ThreadRng;
+use crate::server::rng::RandomNumberGenerator;
+use serde::{Deserialize, Serialize};
 
+pub mod area_starting_points;
 pub mod basic_builders;
+pub mod bsp;
+pub mod cellular_automata;
+pub mod cull_unreachable;
+pub mod distant_exit;
 pub mod drunkard;
 pub mod factories;
+pub mod prefab;
 pub mod shop_builder;
+pub mod spawner;
+pub mod waveform_collapse;
 
 // Most of this taken from https://bfnightly.bracketproductions.com/rustbook/chapter_36.html
 pub trait BaseMapBuilder {
-    fn build(&mut self, rng: &mut ThreadRng, build_data: &mut BuiltMap);
+    fn build(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap);
 }
 
 pub trait MetaMapBuilder {
-    fn mutate(&mut self, rng: &mut ThreadRng, build_data: &mut BuiltMap);
+    fn mutate(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap);
 }
 
 pub struct MapBuilder {
@@ -41,7 +50,7 @@ impl MapBuilder {
         self
     }
 
-    pub fn build(mut self, rng: &mut ThreadRng) -> BuiltMap {
+    pub fn build(mut self, rng: &mut RandomNumberGenerator) -> BuiltMap {
         self.base.build(rng, &mut self.build_data);
         for mut metabuilder in self.builders.drain(..) {
             metabuilder.mutate(rng, &mut self.build_data)
@@ -50,6 +59,7 @@ impl MapBuilder {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BuiltMap {
     pub spawn_list: Vec<(usize, String)>,
     pub map: Map,
@@ -76,9 +86,16 @@ impl BuiltMap {
     fn take_snapshot(&mut self) {
         if self.with_history {
             let mut snapshot = self.map.clone();
+            // Show the whole map while it's being built.
             for tile in snapshot.revealed_tiles.iter_mut() {
                 *tile = true;
             }
+            for tile in snapshot.visible_tiles.iter_mut() {
+                *tile = true;
+            }
+            for light in snapshot.light.iter_mut() {
+                *light = [1.0; 3];
+            }
             self.history.push(snapshot);
         }
     }
@@ -0,0 +1,294 @@
+use crate::error::{Error, Result};
+use crate::geom::Point;
+use crate::map::TileType;
+use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+use quicksilver::load_file;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// What a character in a prefab layout stands for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrefabCell {
+    pub tile: TileType,
+    /// Id of an entity from `entities.json` to put on the tile.
+    pub entity: Option<String>,
+}
+
+/// A hand-drawn piece of map. Cells left as `None` keep whatever the map already had.
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    pub name: String,
+    /// Which maps the prefab is meant for, like `shop` or `vault`.
+    pub tag: String,
+    pub width: i32,
+    pub height: i32,
+    cells: Vec<Option<PrefabCell>>,
+}
+
+impl Prefab {
+    pub fn cell(&self, x: i32, y: i32) -> Option<&PrefabCell> {
+        self.cells[(y * self.width + x) as usize].as_ref()
+    }
+}
+
+/// Every prefab described in `data/prefabs.txt`.
+///
+/// The file starts with a legend, one character per line followed by the tile type it
+/// stands for and optionally an entity id. Each prefab then follows a `prefab <name> <tag>`
+/// line, drawn with the legend's characters. Spaces leave the map as it was and lines
+/// starting with `//` are ignored:
+///   legend
+///   # Wall
+///   . Floor
+///   d Floor display
+///   prefab display_nook shop
+///   #####
+///   #d.d#
+#[derive(Clone, Debug, Default)]
+pub struct Prefabs {
+    prefabs: Vec<Prefab>,
+}
+
+impl Prefabs {
+    pub async fn load() -> Self {
+        let file_contents = load_file("data/prefabs.txt")
+            .await
+            .expect("Couldn't find prefab file");
+        let raw_string =
+            std::str::from_utf8(&file_contents).expect("Couldn't get raw string from prefab file");
+        Self::parse(raw_string).expect("Invalid prefab file")
+    }
+
+    pub fn load_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let raw_string = std::fs::read_to_string(path)?;
+        Self::parse(&raw_string)
+    }
+
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut legend = HashMap::new();
+        let mut prefabs = vec![];
+        let mut current: Option<(String, String, Vec<&str>)> = None;
+        let mut in_legend = false;
+        for (index, line) in source.lines().enumerate() {
+            let error = |reason: String| Error::Prefab(format!("line {}: {}", index + 1, reason));
+            if line.trim_start().starts_with("//") {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("legend") if words.next().is_none() => {
+                    in_legend = true;
+                    continue;
+                }
+                Some("prefab") => {
+                    let (name, tag) = match (words.next(), words.next(), words.next()) {
+                        (Some(name), Some(tag), None) => (name.to_string(), tag.to_string()),
+                        _ => return Err(error("expected prefab <name> <tag>".to_string())),
+                    };
+                    if let Some(prefab) = current.take() {
+                        prefabs.push(build_prefab(prefab, &legend)?);
+                    }
+                    current = Some((name, tag, vec![]));
+                    in_legend = false;
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some((_, _, rows)) = current.as_mut() {
+                rows.push(line.trim_end());
+            } else if in_legend && !line.trim().is_empty() {
+                let (ch, cell) = parse_legend(line).map_err(error)?;
+                legend.insert(ch, cell);
+            } else if !line.trim().is_empty() {
+                return Err(error(format!("unexpected {:?}", line)));
+            }
+        }
+        if let Some(prefab) = current.take() {
+            prefabs.push(build_prefab(prefab, &legend)?);
+        }
+        Ok(Prefabs { prefabs })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.iter().find(|prefab| prefab.name == name)
+    }
+
+    /// Every prefab meant for `tag` maps, in the order they appear in the file.
+    pub fn tagged(&self, tag: &str) -> Vec<Prefab> {
+        self.prefabs
+            .iter()
+            .filter(|prefab| prefab.tag == tag)
+            .cloned()
+            .collect()
+    }
+}
+
+fn parse_legend(line: &str) -> std::result::Result<(char, PrefabCell), String> {
+    let mut chars = line.chars();
+    let ch = chars.next().unwrap();
+    let mut words = chars.as_str().split_whitespace();
+    let tile = words.next().ok_or("missing tile type")?;
+    let tile = serde_json::from_value(serde_json::Value::String(tile.to_string()))
+        .map_err(|_| format!("unknown tile type {:?}", tile))?;
+    let entity = words.next().map(|entity| entity.to_string());
+    if words.next().is_some() {
+        return Err(format!("too many words in legend entry {:?}", line));
+    }
+    Ok((ch, PrefabCell { tile, entity }))
+}
+
+fn build_prefab(
+    (name, tag, mut rows): (String, String, Vec<&str>),
+    legend: &HashMap<char, PrefabCell>,
+) -> Result<Prefab> {
+    while rows.last().map_or(false, |row| row.is_empty()) {
+        rows.pop();
+    }
+    let width = rows
+        .iter()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0) as i32;
+    let height = rows.len() as i32;
+    if width == 0 {
+        return Err(Error::Prefab(format!("prefab {} is empty", name)));
+    }
+    let mut cells = vec![];
+    for row in rows.iter() {
+        let mut chars = row.chars();
+        for _ in 0..width {
+            cells.push(match chars.next() {
+                None | Some(' ') => None,
+                Some(ch) => Some(legend.get(&ch).cloned().ok_or_else(|| {
+                    Error::Prefab(format!(
+                        "prefab {} uses {:?}, which isn't in the legend",
+                        name, ch
+                    ))
+                })?),
+            });
+        }
+    }
+    Ok(Prefab {
+        name,
+        tag,
+        width,
+        height,
+        cells,
+    })
+}
+
+/// Stamps each prefab once somewhere it fits: over plain floor, away from the map's edge,
+/// the starting position and anything stamped before it. Prefabs with nowhere to go are
+/// left out.
+pub struct PrefabBuilder {
+    pub prefabs: Vec<Prefab>,
+}
+
+impl MetaMapBuilder for PrefabBuilder {
+    fn mutate(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        let mut claimed = HashSet::new();
+        for prefab in self.prefabs.iter() {
+            let spots = self.fitting_spots(prefab, build_data, &claimed);
+            if spots.is_empty() {
+                continue;
+            }
+            let origin = spots[rng.gen_range(0, spots.len())];
+            for y in 0..prefab.height {
+                for x in 0..prefab.width {
+                    if let Some(cell) = prefab.cell(x, y) {
+                        let point = Point::new(origin.x + x, origin.y + y);
+                        build_data.map.set_type(point, cell.tile);
+                        claimed.insert(point);
+                        if let Some(entity) = &cell.entity {
+                            let index = build_data.map.point_to_index(point);
+                            build_data.spawn_list.push((index, entity.clone()));
+                        }
+                    }
+                }
+            }
+            build_data.take_snapshot();
+        }
+    }
+}
+
+impl PrefabBuilder {
+    fn fitting_spots(
+        &self,
+        prefab: &Prefab,
+        build_data: &BuiltMap,
+        claimed: &HashSet<Point>,
+    ) -> Vec<Point> {
+        let map = &build_data.map;
+        let mut spots = vec![];
+        for top in 1..map.size.y - prefab.height {
+            for left in 1..map.size.x - prefab.width {
+                let fits = (0..prefab.height).all(|y| {
+                    (0..prefab.width).all(|x| {
+                        let point = Point::new(left + x, top + y);
+                        prefab.cell(x, y).is_none()
+                            || (map.get_type(point) == TileType::Floor
+                                && !claimed.contains(&point)
+                                && build_data.starting_position != Some(point))
+                    })
+                });
+                if fits {
+                    spots.push(Point::new(left, top));
+                }
+            }
+        }
+        spots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PrefabBuilder, Prefabs};
+    use crate::geom::Rect;
+    use crate::map::TileType;
+    use crate::server::map_builders::shop_builder::create_room;
+    use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+    use crate::server::rng::RandomNumberGenerator;
+
+    const SOURCE: &str = "// test prefabs\nlegend\n# Wall\n. Floor\nd Floor display\n\nprefab nook shop\n###\n#d#\n# #\n\nprefab pillar vault\n#\n";
+
+    #[test]
+    fn test_parse_prefabs() {
+        let prefabs = Prefabs::parse(SOURCE).unwrap();
+        let nook = prefabs.get("nook").unwrap();
+        assert_eq!((nook.width, nook.height), (3, 3));
+        assert_eq!(nook.cell(0, 0).unwrap().tile, TileType::Wall);
+        assert_eq!(nook.cell(1, 1).unwrap().entity, Some("display".to_string()));
+        assert!(nook.cell(1, 2).is_none());
+        assert_eq!(prefabs.tagged("vault").len(), 1);
+        assert!(Prefabs::parse("legend\n# Wall\nprefab bad shop\n#x#\n").is_err());
+        assert!(Prefabs::parse("legend\n# Lava\n").is_err());
+    }
+
+    #[test]
+    fn test_prefabs_stamp_over_floor() {
+        let prefabs = Prefabs::parse(SOURCE).unwrap();
+        let mut build_data = BuiltMap::new((6, 6).into(), 0);
+        create_room(
+            &mut build_data.map,
+            &Rect::new((1, 1).into(), (4, 4).into()),
+        );
+        build_data.starting_position = Some((4, 4).into());
+        PrefabBuilder {
+            prefabs: prefabs.tagged("shop"),
+        }
+        .mutate(&mut RandomNumberGenerator::seeded(3), &mut build_data);
+
+        assert_eq!(build_data.spawn_list.len(), 1);
+        let (index, entity) = &build_data.spawn_list[0];
+        assert_eq!(entity, "display");
+        let point = build_data.map.index_to_point(*index);
+        assert_eq!(build_data.map.get_type(point), TileType::Floor);
+        assert_eq!(
+            build_data.map.get_type((point.x, point.y - 1).into()),
+            TileType::Wall
+        );
+        assert_eq!(build_data.map.get_type((4, 4).into()), TileType::Floor);
+    }
+}
@@ -1,18 +1,45 @@
 use crate::geom::Rect;
 use crate::map::{Map, TileType};
 use crate::server::map_builders::{BaseMapBuilder, BuiltMap};
-use rand::prelude::ThreadRng;
+use crate::server::rng::RandomNumberGenerator;
 
 pub struct ShopBuilder;
 
 impl BaseMapBuilder for ShopBuilder {
-    fn build(&mut self, _: &mut ThreadRng, build_data: &mut BuiltMap) {
+    fn build(&mut self, _: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
         let size: (i32, i32) = build_data.map.size.to_tuple();
-        let map = &mut build_data.map;
         create_room(
-            map,
+            &mut build_data.map,
             &Rect::new((1, 1).into(), (size.0 - 2, size.1 - 2).into()),
         );
+        build_data.take_snapshot();
+
+        // A gap in the bottom wall for customers to come and go through, with a window
+        // either side of it.
+        let map = &mut build_data.map;
+        map.set_type((size.0 / 2, size.1 - 1).into(), TileType::Entrance);
+        map.set_type((size.0 / 2 - 3, size.1 - 1).into(), TileType::Window);
+        map.set_type((size.0 / 2 + 3, size.1 - 1).into(), TileType::Window);
+        build_data.take_snapshot();
+
+        // A back room in the top right corner, with the way down to the stock room
+        // levels behind its door.
+        let map = &mut build_data.map;
+        let back_wall = size.0 - 6;
+        for y in 1..6 {
+            map.set_type((back_wall, y).into(), TileType::Wall);
+        }
+        for x in back_wall..size.0 - 1 {
+            map.set_type((x, 6).into(), TileType::Wall);
+        }
+        map.set_type((back_wall, 3).into(), TileType::DoorClosed);
+        map.set_type((size.0 - 3, 2).into(), TileType::DownStairs);
+        build_data.take_snapshot();
+
+        for x in 3..8 {
+            build_data.map.set_type((x, 5).into(), TileType::Counter);
+        }
+        build_data.take_snapshot();
 
         build_data.starting_position = Some((size.0 / 2, size.1 / 2).into());
     }
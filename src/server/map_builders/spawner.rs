@@ -0,0 +1,239 @@
+use crate::geom::{Point, Rect};
+use crate::map::TileType;
+use crate::server::map_builders::{BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+use quicksilver::load_file;
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::from_str;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Maps without rooms are carved up into squares this size for spawning.
+const AREA_SIZE: i32 = 8;
+
+#[derive(Deserialize, Debug, Clone)]
+struct Data {
+    tables: Vec<SpawnTable>,
+}
+
+/// One kind of entity a table can spawn.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SpawnEntry {
+    /// Id of the entity in `entities.json`.
+    pub id: String,
+    pub weight: i32,
+    /// Added to `weight` once for every level of depth, so finds can get rarer or more
+    /// common the deeper the player goes.
+    #[serde(default)]
+    pub depth_weight: i32,
+    #[serde(default)]
+    pub min_depth: i32,
+    pub max_depth: Option<i32>,
+}
+
+impl SpawnEntry {
+    fn weight_at(&self, depth: i32) -> i32 {
+        if depth < self.min_depth || self.max_depth.map_or(false, |max| depth > max) {
+            return 0;
+        }
+        (self.weight + self.depth_weight * depth).max(0)
+    }
+}
+
+/// What can turn up in each room or area of a map, and how much of it.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SpawnTable {
+    pub name: String,
+    pub min_spawns: i32,
+    pub max_spawns: i32,
+    pub entries: Vec<SpawnEntry>,
+}
+
+impl SpawnTable {
+    /// Picks an entity id at random, weighted for `depth`. Returns `None` if nothing in
+    /// the table can spawn that deep.
+    pub fn roll(&self, depth: i32, rng: &mut RandomNumberGenerator) -> Option<&str> {
+        let total: i32 = self
+            .entries
+            .iter()
+            .map(|entry| entry.weight_at(depth))
+            .sum();
+        if total <= 0 {
+            return None;
+        }
+        let mut roll = rng.gen_range(0, total);
+        for entry in self.entries.iter() {
+            let weight = entry.weight_at(depth);
+            if roll < weight {
+                return Some(&entry.id);
+            }
+            roll -= weight;
+        }
+        None
+    }
+}
+
+/// Every spawn table, as described in `data/spawn_tables.json`.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnTables {
+    tables: Vec<SpawnTable>,
+}
+
+impl SpawnTables {
+    pub async fn load() -> Self {
+        let file_contents = load_file("data/spawn_tables.json")
+            .await
+            .expect("Couldn't find spawn table file");
+        let raw_string = std::str::from_utf8(&file_contents)
+            .expect("Couldn't get raw string from spawn table file");
+        Self::from_json(raw_string)
+    }
+
+    pub fn load_from_path(path: impl AsRef<Path>) -> crate::error::Result<Self> {
+        let raw_string = std::fs::read_to_string(path)?;
+        Ok(Self::from_json(&raw_string))
+    }
+
+    pub fn from_json(raw_string: &str) -> Self {
+        let data: Data = from_str(raw_string).expect("Invalid spawn table file");
+        SpawnTables {
+            tables: data.tables,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> &SpawnTable {
+        self.tables
+            .iter()
+            .find(|table| table.name == name)
+            .unwrap_or_else(|| panic!("No spawn table called {:?}", name))
+    }
+}
+
+/// Fills `spawn_list` from a spawn table, rolling for every room when the base builder
+/// made rooms and for every square area of the map otherwise. Entities only go on plain
+/// floor that nothing else has claimed.
+pub struct AreaSpawner {
+    pub table: SpawnTable,
+}
+
+impl MetaMapBuilder for AreaSpawner {
+    fn mutate(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        let areas = match &build_data.rooms {
+            Some(rooms) => rooms.clone(),
+            None => self.square_areas(build_data),
+        };
+        let mut taken = build_data
+            .spawn_list
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<HashSet<_>>();
+        if let Some(start) = build_data.starting_position {
+            taken.insert(build_data.map.point_to_index(start));
+        }
+
+        let depth = build_data.map.depth;
+        for area in areas {
+            let mut free = self.free_tiles(build_data, &area, &taken);
+            let count = rng.gen_range(self.table.min_spawns, self.table.max_spawns + 1);
+            for _ in 0..count {
+                if free.is_empty() {
+                    break;
+                }
+                let index = free.remove(rng.gen_range(0, free.len()));
+                if let Some(id) = self.table.roll(depth, rng) {
+                    taken.insert(index);
+                    build_data.spawn_list.push((index, id.to_string()));
+                }
+            }
+        }
+    }
+}
+
+impl AreaSpawner {
+    fn square_areas(&self, build_data: &BuiltMap) -> Vec<Rect> {
+        let size = build_data.map.size;
+        let mut areas = vec![];
+        for y in (0..size.y).step_by(AREA_SIZE as usize) {
+            for x in (0..size.x).step_by(AREA_SIZE as usize) {
+                let (width, height) = (AREA_SIZE.min(size.x - x), AREA_SIZE.min(size.y - y));
+                areas.push(Rect::new((x, y).into(), (width, height).into()));
+            }
+        }
+        areas
+    }
+
+    fn free_tiles(&self, build_data: &BuiltMap, area: &Rect, taken: &HashSet<usize>) -> Vec<usize> {
+        let map = &build_data.map;
+        let mut free = vec![];
+        for y in area.min_y()..area.max_y() {
+            for x in area.min_x()..area.max_x() {
+                let point = Point::new(x, y);
+                if !map.in_bounds(point) || map.get_type(point) != TileType::Floor {
+                    continue;
+                }
+                let index = map.point_to_index(point);
+                if !taken.contains(&index) {
+                    free.push(index);
+                }
+            }
+        }
+        free
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AreaSpawner, SpawnTables};
+    use crate::map::TileType;
+    use crate::server::map_builders::factories::drunk_builder;
+    use crate::server::map_builders::MetaMapBuilder;
+    use crate::server::rng::RandomNumberGenerator;
+    use std::collections::HashSet;
+
+    const SOURCE: &str = r#"{
+      "tables": [
+        {
+          "name": "test",
+          "min_spawns": 1,
+          "max_spawns": 2,
+          "entries": [
+            { "id": "shallow", "weight": 5, "max_depth": 2 },
+            { "id": "deep", "weight": 0, "depth_weight": 2, "min_depth": 3 }
+          ]
+        }
+      ]
+    }"#;
+
+    #[test]
+    fn test_rolls_scale_with_depth() {
+        let table = SpawnTables::from_json(SOURCE).get("test").clone();
+        let mut rng = RandomNumberGenerator::seeded(1);
+        for _ in 0..20 {
+            assert_eq!(table.roll(1, &mut rng), Some("shallow"));
+            assert_eq!(table.roll(4, &mut rng), Some("deep"));
+        }
+        let mut shallow_only = table.clone();
+        shallow_only.entries.truncate(1);
+        assert_eq!(shallow_only.roll(3, &mut rng), None);
+    }
+
+    #[test]
+    fn test_spawns_land_on_free_floor() {
+        let table = SpawnTables::from_json(SOURCE).get("test").clone();
+        let mut rng = RandomNumberGenerator::seeded(5);
+        let mut built_map = drunk_builder((60, 40).into(), 1, false, &mut rng);
+        AreaSpawner { table }.mutate(&mut rng, &mut built_map);
+
+        assert!(!built_map.spawn_list.is_empty());
+        let start = built_map.starting_position.unwrap();
+        let mut seen = HashSet::new();
+        for (index, id) in built_map.spawn_list.iter() {
+            assert_eq!(id, "shallow");
+            assert!(seen.insert(*index));
+            let point = built_map.map.index_to_point(*index);
+            assert_ne!(point, start);
+            assert_eq!(built_map.map.get_type(point), TileType::Floor);
+        }
+    }
+}
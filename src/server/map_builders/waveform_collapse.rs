@@ -0,0 +1,465 @@
+use crate::map::TileType;
+use crate::server::map_builders::area_starting_points::{AreaStartingPosition, XStart, YStart};
+use crate::server::map_builders::prefab::Prefab;
+use crate::server::map_builders::{BaseMapBuilder, BuiltMap, MetaMapBuilder};
+use crate::server::rng::RandomNumberGenerator;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// Up, right, down and left, in the order compatibility is stored.
+const DIRECTIONS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+/// Fresh starts to try before giving up and leaving the map as it was.
+const MAX_ATTEMPTS: u32 = 5;
+/// Choices to take back within one attempt before starting over.
+const MAX_BACKTRACKS: u32 = 500;
+/// Choices made between history snapshots. Each one usually settles a lot of tiles.
+const SNAPSHOT_INTERVAL: u32 = 10;
+
+/// Where the builder learns which tiles can sit next to which.
+pub enum Sample {
+    /// Whatever the builders before this one made.
+    Map,
+    /// A prefab, with its see-through cells read as wall.
+    Prefab(Prefab),
+}
+
+/// Makes a new map that looks like a sample by wave function collapse. Every square of
+/// `pattern_size` tiles in the result also appears somewhere in the sample. When a choice
+/// leads to a dead end it's taken back and something else is tried.
+///
+/// As a base builder it needs a prefab sample, since there's no map to learn from yet.
+pub struct WaveformCollapseBuilder {
+    pub pattern_size: i32,
+    pub sample: Sample,
+}
+
+// https://github.com/mxgmn/WaveFunctionCollapse
+impl MetaMapBuilder for WaveformCollapseBuilder {
+    fn mutate(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        let patterns = match &self.sample {
+            Sample::Map => {
+                let (width, height) = build_data.map.size.to_tuple();
+                Patterns::learn(width, height, &build_data.map.tiles, self.pattern_size)
+            }
+            Sample::Prefab(prefab) => {
+                let tiles = (0..prefab.height)
+                    .flat_map(|y| (0..prefab.width).map(move |x| (x, y)))
+                    .map(|(x, y)| prefab.cell(x, y).map_or(TileType::Wall, |cell| cell.tile))
+                    .collect::<Vec<_>>();
+                Patterns::learn(prefab.width, prefab.height, &tiles, self.pattern_size)
+            }
+        };
+        let (width, height) = build_data.map.size.to_tuple();
+        if patterns.weights.is_empty() || width < self.pattern_size || height < self.pattern_size {
+            return;
+        }
+
+        let original = build_data.map.tiles.clone();
+        for _ in 0..MAX_ATTEMPTS {
+            if let Some(solution) = collapse(&patterns, rng, build_data) {
+                build_data.map.tiles = solution;
+                self.finish(rng, build_data);
+                return;
+            }
+        }
+        build_data.map.tiles = original;
+    }
+}
+
+impl BaseMapBuilder for WaveformCollapseBuilder {
+    fn build(&mut self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        self.mutate(rng, build_data);
+    }
+}
+
+impl WaveformCollapseBuilder {
+    /// Walls the edge back in and drops anything the new layout no longer has room for.
+    fn finish(&self, rng: &mut RandomNumberGenerator, build_data: &mut BuiltMap) {
+        let map = &mut build_data.map;
+        let (width, height) = map.size.to_tuple();
+        for x in 0..width {
+            map.set_type((x, 0).into(), TileType::Wall);
+            map.set_type((x, height - 1).into(), TileType::Wall);
+        }
+        for y in 0..height {
+            map.set_type((0, y).into(), TileType::Wall);
+            map.set_type((width - 1, y).into(), TileType::Wall);
+        }
+        let tiles = &map.tiles;
+        build_data
+            .spawn_list
+            .retain(|(index, _)| tiles[*index] == TileType::Floor);
+        build_data.rooms = None;
+        let start_is_floor = build_data.starting_position.map_or(false, |start| {
+            build_data.map.get_type(start) == TileType::Floor
+        });
+        if !start_is_floor {
+            AreaStartingPosition {
+                x: XStart::Center,
+                y: YStart::Center,
+            }
+            .mutate(rng, build_data);
+        }
+        build_data.take_snapshot();
+    }
+}
+
+/// Every distinct square of tiles in the sample, how often each turns up and which ones
+/// can overlap which.
+struct Patterns {
+    size: i32,
+    tiles: Vec<Vec<TileType>>,
+    weights: Vec<f32>,
+    /// For each pattern and direction, the patterns that can be next to it that way.
+    compatible: Vec<[Vec<u64>; 4]>,
+}
+
+impl Patterns {
+    fn learn(width: i32, height: i32, sample: &[TileType], size: i32) -> Self {
+        let mut index = HashMap::new();
+        let mut tiles: Vec<Vec<TileType>> = vec![];
+        let mut weights = vec![];
+        for y in 0..=height - size {
+            for x in 0..=width - size {
+                let pattern = (0..size)
+                    .flat_map(|dy| (0..size).map(move |dx| (x + dx, y + dy)))
+                    .map(|(x, y)| sample[(y * width + x) as usize])
+                    .collect::<Vec<_>>();
+                let id = *index.entry(pattern.clone()).or_insert_with(|| {
+                    tiles.push(pattern);
+                    weights.push(0.0);
+                    tiles.len() - 1
+                });
+                weights[id] += 1.0;
+            }
+        }
+
+        let words = words(tiles.len());
+        let compatible = tiles
+            .iter()
+            .map(|a| {
+                let mut allowed: [Vec<u64>; 4] = Default::default();
+                for (direction, offset) in DIRECTIONS.iter().enumerate() {
+                    allowed[direction] = vec![0; words];
+                    for (id, b) in tiles.iter().enumerate() {
+                        if overlaps_agree(a, b, size, *offset) {
+                            allowed[direction][id / 64] |= 1 << (id % 64);
+                        }
+                    }
+                }
+                allowed
+            })
+            .collect();
+        Patterns {
+            size,
+            tiles,
+            weights,
+            compatible,
+        }
+    }
+}
+
+fn words(patterns: usize) -> usize {
+    (patterns + 63) / 64
+}
+
+/// Whether pattern `b` can sit at `offset` from pattern `a`, with the tiles they share
+/// matching.
+fn overlaps_agree(a: &[TileType], b: &[TileType], size: i32, offset: (i32, i32)) -> bool {
+    for y in 0..size {
+        for x in 0..size {
+            let (bx, by) = (x - offset.0, y - offset.1);
+            if bx < 0 || by < 0 || bx >= size || by >= size {
+                continue;
+            }
+            if a[(y * size + x) as usize] != b[(by * size + bx) as usize] {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn patterns_in(bits: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    bits.iter().enumerate().flat_map(|(word, bits)| {
+        (0..64)
+            .filter(move |bit| bits & (1 << bit) != 0)
+            .map(move |bit| word * 64 + bit)
+    })
+}
+
+/// Which patterns each cell could still take. Cells are the top left corners of the
+/// patterns laid over the map, so there are `pattern_size - 1` fewer of them each way.
+struct Wave<'a> {
+    patterns: &'a Patterns,
+    width: i32,
+    height: i32,
+    cells: Vec<Vec<u64>>,
+    entropy: Vec<f32>,
+    /// Every change made to a cell, with what it was before, so choices can be undone.
+    trail: Vec<(usize, Vec<u64>)>,
+}
+
+impl<'a> Wave<'a> {
+    fn new(patterns: &'a Patterns, width: i32, height: i32) -> Self {
+        let count = patterns.weights.len();
+        let mut all = vec![0; words(count)];
+        for id in 0..count {
+            all[id / 64] |= 1 << (id % 64);
+        }
+        let cells = vec![all; (width * height) as usize];
+        let mut wave = Wave {
+            patterns,
+            width,
+            height,
+            entropy: vec![0.0; cells.len()],
+            cells,
+            trail: vec![],
+        };
+        for cell in 0..wave.cells.len() {
+            wave.update_entropy(cell);
+        }
+        wave
+    }
+
+    fn count(&self, cell: usize) -> u32 {
+        self.cells[cell].iter().map(|bits| bits.count_ones()).sum()
+    }
+
+    fn update_entropy(&mut self, cell: usize) {
+        let (mut total, mut total_log) = (0.0, 0.0);
+        for id in patterns_in(&self.cells[cell]) {
+            let weight = self.patterns.weights[id];
+            total += weight;
+            total_log += weight * weight.ln();
+        }
+        self.entropy[cell] = if total > 0.0 {
+            total.ln() - total_log / total
+        } else {
+            0.0
+        };
+    }
+
+    fn set(&mut self, cell: usize, bits: Vec<u64>) {
+        let old = std::mem::replace(&mut self.cells[cell], bits);
+        self.trail.push((cell, old));
+        self.update_entropy(cell);
+    }
+
+    fn undo(&mut self, to: usize) {
+        while self.trail.len() > to {
+            let (cell, bits) = self.trail.pop().unwrap();
+            self.cells[cell] = bits;
+            self.update_entropy(cell);
+        }
+    }
+
+    /// Narrows down the neighbours of every cell in `changed`, and theirs in turn.
+    /// Returns false if some cell is left with nothing it could be.
+    fn propagate(&mut self, mut changed: Vec<usize>) -> bool {
+        let words = words(self.patterns.weights.len());
+        while let Some(cell) = changed.pop() {
+            let (x, y) = (cell as i32 % self.width, cell as i32 / self.width);
+            for (direction, (dx, dy)) in DIRECTIONS.iter().enumerate() {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= self.width || ny >= self.height {
+                    continue;
+                }
+                let mut allowed = vec![0; words];
+                for id in patterns_in(&self.cells[cell]) {
+                    let compatible = &self.patterns.compatible[id][direction];
+                    for (word, bits) in allowed.iter_mut().zip(compatible) {
+                        *word |= bits;
+                    }
+                }
+                let neighbour = (ny * self.width + nx) as usize;
+                let narrowed = self.cells[neighbour]
+                    .iter()
+                    .zip(&allowed)
+                    .map(|(bits, allowed)| bits & allowed)
+                    .collect::<Vec<_>>();
+                if narrowed == self.cells[neighbour] {
+                    continue;
+                }
+                if narrowed.iter().all(|bits| *bits == 0) {
+                    return false;
+                }
+                self.set(neighbour, narrowed);
+                changed.push(neighbour);
+            }
+        }
+        true
+    }
+
+    /// The undecided cell with the fewest likely options, with a little noise so ties
+    /// don't always go to the top left.
+    fn most_constrained(&self, rng: &mut RandomNumberGenerator) -> Option<usize> {
+        let mut best: Option<(usize, f32)> = None;
+        for cell in 0..self.cells.len() {
+            if self.count(cell) <= 1 {
+                continue;
+            }
+            let entropy = self.entropy[cell] + rng.gen::<f32>() * 1e-3;
+            if best.map_or(true, |(_, lowest)| entropy < lowest) {
+                best = Some((cell, entropy));
+            }
+        }
+        best.map(|(cell, _)| cell)
+    }
+
+    /// Picks one of the patterns `cell` could still take, favouring common ones.
+    fn choose(&self, cell: usize, rng: &mut RandomNumberGenerator) -> usize {
+        let options = patterns_in(&self.cells[cell]).collect::<Vec<_>>();
+        let total: f32 = options.iter().map(|id| self.patterns.weights[*id]).sum();
+        let mut roll = rng.gen::<f32>() * total;
+        for id in options.iter() {
+            roll -= self.patterns.weights[*id];
+            if roll < 0.0 {
+                return *id;
+            }
+        }
+        *options.last().unwrap()
+    }
+
+    /// The map as far as it's been decided, with undecided tiles left as `Digging`.
+    fn tiles(&self, map_width: i32, map_height: i32) -> Vec<TileType> {
+        let size = self.patterns.size;
+        let mut tiles = vec![];
+        for y in 0..map_height {
+            for x in 0..map_width {
+                let (cx, cy) = (x.min(self.width - 1), y.min(self.height - 1));
+                let cell = (cy * self.width + cx) as usize;
+                let options = patterns_in(&self.cells[cell]).collect::<Vec<_>>();
+                let tile = match options.as_slice() {
+                    [id] => self.patterns.tiles[*id][((y - cy) * size + x - cx) as usize],
+                    _ => TileType::Digging,
+                };
+                tiles.push(tile);
+            }
+        }
+        tiles
+    }
+}
+
+/// One go at filling the whole map. Returns `None` if it runs into too many dead ends.
+fn collapse(
+    patterns: &Patterns,
+    rng: &mut RandomNumberGenerator,
+    build_data: &mut BuiltMap,
+) -> Option<Vec<TileType>> {
+    let (width, height) = build_data.map.size.to_tuple();
+    let mut wave = Wave::new(
+        patterns,
+        width - patterns.size + 1,
+        height - patterns.size + 1,
+    );
+    // Some patterns can't have anything on one side of them at all.
+    if !wave.propagate((0..wave.cells.len()).collect()) {
+        return None;
+    }
+
+    // Each choice made, along with how long the trail was before it.
+    let mut choices: Vec<(usize, usize, usize)> = vec![];
+    let mut backtracks = 0;
+    let mut chosen = 0;
+    while let Some(cell) = wave.most_constrained(rng) {
+        let id = wave.choose(cell, rng);
+        choices.push((cell, id, wave.trail.len()));
+        let mut bits = vec![0; wave.cells[cell].len()];
+        bits[id / 64] |= 1 << (id % 64);
+        wave.set(cell, bits);
+
+        let mut consistent = wave.propagate(vec![cell]);
+        while !consistent {
+            backtracks += 1;
+            if backtracks > MAX_BACKTRACKS {
+                return None;
+            }
+            // Take the last choice back and rule it out. That follows from the choices
+            // before it, so it stays on the trail for them.
+            let (cell, id, mark) = choices.pop()?;
+            wave.undo(mark);
+            let mut bits = wave.cells[cell].clone();
+            bits[id / 64] &= !(1 << (id % 64));
+            if bits.iter().all(|bits| *bits == 0) {
+                continue;
+            }
+            wave.set(cell, bits);
+            consistent = wave.propagate(vec![cell]);
+        }
+
+        chosen += 1;
+        if build_data.with_history && chosen % SNAPSHOT_INTERVAL == 0 {
+            build_data.map.tiles = wave.tiles(width, height);
+            build_data.take_snapshot();
+        }
+    }
+    Some(wave.tiles(width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sample, WaveformCollapseBuilder};
+    use crate::map::TileType;
+    use crate::server::map_builders::prefab::Prefabs;
+    use crate::server::map_builders::{BaseMapBuilder, BuiltMap};
+    use crate::server::rng::RandomNumberGenerator;
+    use std::collections::HashSet;
+
+    const SAMPLE: &str = "legend\n# Wall\n. Floor\nprefab sample test\n\
+        ###########\n\
+        #....#....#\n\
+        #....#....#\n\
+        #.........#\n\
+        ##.####.###\n\
+        #......#..#\n\
+        #......#..#\n\
+        #.........#\n\
+        ###########\n";
+
+    #[test]
+    fn test_output_only_uses_sample_patterns() {
+        let prefab = Prefabs::parse(SAMPLE)
+            .unwrap()
+            .get("sample")
+            .unwrap()
+            .clone();
+        let sample = (0..prefab.height)
+            .flat_map(|y| (0..prefab.width).map(move |x| (x, y)))
+            .map(|(x, y)| prefab.cell(x, y).unwrap().tile)
+            .collect::<Vec<_>>();
+        let window = |tiles: &[TileType], width: i32, x: i32, y: i32| {
+            (0..3)
+                .flat_map(|dy| (0..3).map(move |dx| (x + dx, y + dy)))
+                .map(|(x, y)| tiles[(y * width + x) as usize])
+                .collect::<Vec<_>>()
+        };
+        let mut known = HashSet::new();
+        for y in 0..prefab.height - 2 {
+            for x in 0..prefab.width - 2 {
+                known.insert(window(&sample, prefab.width, x, y));
+            }
+        }
+
+        let mut build_data = BuiltMap::new((24, 16).into(), 1);
+        build_data.with_history = true;
+        WaveformCollapseBuilder {
+            pattern_size: 3,
+            sample: Sample::Prefab(prefab),
+        }
+        .build(&mut RandomNumberGenerator::seeded(4), &mut build_data);
+
+        let tiles = &build_data.map.tiles;
+        assert!(tiles.contains(&TileType::Floor));
+        assert!(!tiles.contains(&TileType::Digging));
+        assert!(build_data.history.len() > 1);
+        // The border is walled in afterwards, so only check squares clear of it.
+        for y in 1..16 - 4 {
+            for x in 1..24 - 4 {
+                assert!(known.contains(&window(tiles, 24, x, y)));
+            }
+        }
+        let start = build_data.starting_position.unwrap();
+        assert_eq!(build_data.map.get_type(start), TileType::Floor);
+    }
+}
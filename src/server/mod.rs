@@ -1,6 +1,14 @@
+pub mod action;
 pub mod fov;
 pub mod gamestate;
+pub mod host;
+pub mod levels;
 pub mod map_builders;
+pub mod replay;
+pub mod replication;
+pub mod rng;
+pub mod save;
+pub mod script;
 pub mod server;
 pub mod systems;
 pub mod serializers;
\ No newline at end of file
@@ -0,0 +1,219 @@
+use crate::error::{Error, Result};
+use crate::map::Map;
+use crate::network::protocol::Request;
+use crate::server::save;
+use crate::server::server::Server;
+use legion::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher;
+
+/// Bumped whenever `Replay` changes shape.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// How often, in server ticks, a recording stores the world hash.
+pub const CHECKPOINT_INTERVAL: u64 = 500;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RecordedCommand {
+    pub tick: u64,
+    pub request: Request,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub tick: u64,
+    pub hash: u64,
+}
+
+/// Everything needed to play a session back: the seed it started from and every player
+/// command along with the tick it was applied on. Checkpoints hold the world hash seen
+/// while recording so a replay can tell exactly when it drifted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub commands: Vec<RecordedCommand>,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+#[derive(Deserialize)]
+struct ReplayHeader {
+    version: u32,
+}
+
+/// The first checkpoint whose hash didn't match the recording.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Divergence {
+    pub tick: u64,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Self {
+        Replay {
+            version: REPLAY_VERSION,
+            seed,
+            commands: vec![],
+            checkpoints: vec![],
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|err| Error::Replay(err.to_string()))
+    }
+
+    pub fn from_json(raw: &str) -> Result<Replay> {
+        let header: ReplayHeader =
+            serde_json::from_str(raw).map_err(|err| Error::Replay(err.to_string()))?;
+        if header.version != REPLAY_VERSION {
+            return Err(Error::Replay(format!(
+                "replay version {} is not supported (expected {})",
+                header.version, REPLAY_VERSION
+            )));
+        }
+        serde_json::from_str(raw).map_err(|err| Error::Replay(err.to_string()))
+    }
+
+    pub fn record(&mut self, tick: u64, request: Request) {
+        self.commands.push(RecordedCommand { tick, request });
+    }
+
+    pub fn checkpoint(&mut self, tick: u64, hash: u64) {
+        if self.checkpoints.last().map(|c| c.tick) != Some(tick) {
+            self.checkpoints.push(Checkpoint { tick, hash });
+        }
+    }
+
+    /// Feeds the recorded commands to `server`, which must have been created from
+    /// `self.seed` and not ticked yet, checking every checkpoint along the way. Returns
+    /// the tick the replay finished on.
+    pub fn verify(&self, server: &mut Server) -> std::result::Result<u64, Divergence> {
+        let mut commands = self.commands.iter().peekable();
+        let mut checkpoints = self.checkpoints.iter().peekable();
+        loop {
+            // Checkpoints are taken at the end of a tick, before the next tick's
+            // commands are applied.
+            while let Some(checkpoint) = checkpoints.peek() {
+                if checkpoint.tick > server.ticks() {
+                    break;
+                }
+                let actual = server.state_hash();
+                if actual != checkpoint.hash {
+                    return Err(Divergence {
+                        tick: checkpoint.tick,
+                        expected: checkpoint.hash,
+                        actual,
+                    });
+                }
+                checkpoints.next();
+            }
+            while let Some(command) = commands.peek() {
+                if command.tick > server.ticks() {
+                    break;
+                }
+                server.handle_request(command.request);
+                commands.next();
+            }
+            if commands.peek().is_none() && checkpoints.peek().is_none() {
+                return Ok(server.ticks());
+            }
+            server.tick();
+        }
+    }
+}
+
+/// 64-bit FNV-1a. `DefaultHasher` is free to change between Rust releases, which would
+/// invalidate every recorded replay.
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// Hashes everything that should come out the same when a session is replayed. Entity
+/// ids and iteration order are left out since they depend on how the world was built
+/// rather than on what's in it.
+pub fn world_hash(world: &World, map: &Map, tick: u64) -> u64 {
+    let (entities, _) = save::save_entities(world);
+    let mut states = entities
+        .iter()
+        .map(|entity| {
+            let contents = entity.inventory.as_ref().map(|inventory| {
+                inventory
+                    .contents
+                    .iter()
+                    .map(|index| entities[*index].name.name.clone())
+                    .collect::<Vec<_>>()
+            });
+            let state = (
+                &entity.name,
+                &entity.position,
+                &entity.renderable,
+                &entity.priority,
+                &entity.speed,
+                &entity.energy,
+                &entity.active_turn,
+                contents,
+                &entity.value,
+                &entity.wallet,
+                &entity.customer,
+                &entity.customer_spawner,
+                entity.tile_blocker,
+                entity.player,
+                entity.display_cabinet,
+            );
+            serde_json::to_string(&state).unwrap()
+        })
+        .collect::<Vec<_>>();
+    states.sort();
+
+    let mut hasher = StableHasher::default();
+    hasher.write_u64(tick);
+    for state in states {
+        hasher.write(state.as_bytes());
+    }
+    hasher.write(serde_json::to_string(map).unwrap().as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Replay;
+    use crate::network::protocol::Request;
+
+    #[test]
+    fn test_replay_round_trip() {
+        let mut replay = Replay::new(7);
+        replay.record(3, Request::Move { dx: 1, dy: 0 });
+        replay.checkpoint(10, 42);
+        replay.checkpoint(10, 42);
+
+        let loaded = Replay::from_json(&replay.to_json().unwrap()).unwrap();
+        assert_eq!(loaded.seed, 7);
+        assert_eq!(loaded.commands.len(), 1);
+        assert_eq!(loaded.commands[0].tick, 3);
+        assert_eq!(loaded.checkpoints.len(), 1);
+    }
+
+    #[test]
+    fn test_rejects_other_versions() {
+        let raw = r#"{"version":0,"seed":7,"commands":[],"checkpoints":[]}"#;
+        assert!(Replay::from_json(raw).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::component::{
+    DisplayCabinet, Inventory, Name, Player, Position, Renderable, Value, Wallet,
+};
+use crate::map::Map;
+use crate::network::protocol::{
+    EntityState, InventoryState, MapUpdate, NetworkId, TileState, WorldDelta, WorldSnapshot,
+};
+use legion::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// Tracks what a client has already been told so that each tick only the differences
+/// need to be sent.
+#[derive(Default)]
+pub struct Replicator {
+    ids: HashMap<Entity, NetworkId>,
+    next_id: u32,
+    sent_entities: HashMap<NetworkId, EntityState>,
+    sent_map: Option<Map>,
+}
+
+impl Replicator {
+    pub fn new() -> Self {
+        Replicator::default()
+    }
+
+    fn network_id(&mut self, entity: Entity) -> NetworkId {
+        if let Some(id) = self.ids.get(&entity) {
+            return *id;
+        }
+        self.next_id += 1;
+        let id = NetworkId(self.next_id);
+        self.ids.insert(entity, id);
+        id
+    }
+
+    fn capture_entities(&mut self, world: &World) -> Vec<EntityState> {
+        let query = <Read<Name>>::query();
+        let named = query
+            .iter_entities(world)
+            .map(|(entity, name)| (entity, (*name).clone()))
+            .collect::<Vec<_>>();
+
+        let mut states = vec![];
+        for (entity, name) in named {
+            let inventory = match world.get_component::<Inventory>(entity) {
+                Some(inventory) => {
+                    let mut contents = vec![];
+                    for item in inventory.contents.iter() {
+                        contents.push(self.network_id(*item));
+                    }
+                    Some(InventoryState {
+                        contents,
+                        capacity: inventory.capacity,
+                    })
+                }
+                None => None,
+            };
+            states.push(EntityState {
+                id: self.network_id(entity),
+                name,
+                position: world.get_component::<Position>(entity).map(|p| *p),
+                renderable: world.get_component::<Renderable>(entity).map(|r| *r),
+                inventory,
+                value: world.get_component::<Value>(entity).map(|v| *v),
+                wallet: world.get_component::<Wallet>(entity).map(|w| *w),
+                player: world.get_tag::<Player>(entity).is_some(),
+                display_cabinet: world.get_tag::<DisplayCabinet>(entity).is_some(),
+            });
+        }
+        states
+    }
+
+    fn capture_map(map: &Map) -> Map {
+        let mut map = map.clone();
+        map.tile_content.clear();
+        map
+    }
+
+    /// Works out which tiles differ from what the client last saw, bringing `sent_map`
+    /// up to date as it goes. A change of size or depth means a new level, which is sent
+    /// whole.
+    fn map_update(&mut self, map: &Map) -> Option<MapUpdate> {
+        let same_level = match &self.sent_map {
+            Some(sent) => sent.size == map.size && sent.depth == map.depth,
+            None => false,
+        };
+        if !same_level {
+            let map = Self::capture_map(map);
+            self.sent_map = Some(map.clone());
+            return Some(MapUpdate::Whole(map));
+        }
+        let sent = self.sent_map.as_mut().unwrap();
+        let changed = (0..map.tiles.len())
+            .map(|index| TileState::capture(map, index))
+            .filter(|tile| *tile != TileState::capture(sent, tile.index))
+            .collect::<Vec<_>>();
+        if changed.is_empty() {
+            return None;
+        }
+        for tile in changed.iter() {
+            tile.apply(sent);
+        }
+        Some(MapUpdate::Tiles(changed))
+    }
+
+    /// Everything the client needs to build its copy of the world from scratch.
+    pub fn snapshot(&mut self, seed: u64, world: &World, map: &Map) -> WorldSnapshot {
+        let entities = self.capture_entities(world);
+        let map = Self::capture_map(map);
+        self.sent_entities = entities
+            .iter()
+            .map(|state| (state.id, state.clone()))
+            .collect();
+        self.sent_map = Some(map.clone());
+        WorldSnapshot {
+            seed,
+            entities,
+            map,
+        }
+    }
+
+    pub fn delta(&mut self, world: &World, map: &Map) -> Option<WorldDelta> {
+        let mut changed = vec![];
+        let mut alive = HashSet::new();
+        for state in self.capture_entities(world) {
+            alive.insert(state.id);
+            if self.sent_entities.get(&state.id) != Some(&state) {
+                self.sent_entities.insert(state.id, state.clone());
+                changed.push(state);
+            }
+        }
+
+        let removed = self
+            .sent_entities
+            .keys()
+            .filter(|id| !alive.contains(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in removed.iter() {
+            self.sent_entities.remove(id);
+        }
+        self.ids.retain(|_, id| alive.contains(id));
+
+        let map = self.map_update(map);
+
+        if changed.is_empty() && removed.is_empty() && map.is_none() {
+            None
+        } else {
+            Some(WorldDelta {
+                changed,
+                removed,
+                map,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Replicator;
+    use crate::client::replica::ReplicatedWorld;
+    use crate::component::{Inventory, Name, Player, Position};
+    use crate::geom::Point;
+    use crate::map::{Map, TileType};
+    use crate::network::protocol::MapUpdate;
+    use legion::prelude::*;
+
+    fn name(name: &str) -> Name {
+        Name {
+            name: name.to_string(),
+        }
+    }
+
+    /// What a client can see of every entity, in an order that doesn't depend on how
+    /// the world handed out entity ids.
+    fn contents(world: &World) -> Vec<(String, Option<Position>, Vec<String>, bool)> {
+        let query = <Read<Name>>::query();
+        let named = query
+            .iter_entities(world)
+            .map(|(entity, name)| (entity, name.name.clone()))
+            .collect::<Vec<_>>();
+        let mut contents = named
+            .into_iter()
+            .map(|(entity, name)| {
+                let inventory = world
+                    .get_component::<Inventory>(entity)
+                    .map(|inventory| inventory.contents.clone())
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|item| world.get_component::<Name>(*item).unwrap().name.clone())
+                    .collect();
+                let position = world.get_component::<Position>(entity).map(|p| *p);
+                let player = world.get_tag::<Player>(entity).is_some();
+                (name, position, inventory, player)
+            })
+            .collect::<Vec<_>>();
+        contents.sort_by(|a, b| a.0.cmp(&b.0));
+        contents
+    }
+
+    #[test]
+    fn test_snapshot_and_deltas_mirror_the_server() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let mut map = Map::new((6, 4), 1);
+        let items = world
+            .insert((), vec![(name("Heart"),), (name("Club"),)])
+            .to_vec();
+        let player = world.insert(
+            (Player,),
+            vec![(
+                name("Player"),
+                Position { x: 1, y: 1 },
+                Inventory {
+                    contents: vec![items[0]],
+                    capacity: 2,
+                },
+            )],
+        )[0];
+
+        let mut replicator = Replicator::new();
+        let mut replica = ReplicatedWorld::new();
+        replica.apply_snapshot(replicator.snapshot(7, &world, &map));
+        assert_eq!(contents(replica.world()), contents(&world));
+        assert!(replicator.delta(&world, &map).is_none());
+
+        *world.get_component_mut::<Position>(player).unwrap() = Position { x: 2, y: 1 };
+        *world.get_component_mut::<Inventory>(player).unwrap() = Inventory {
+            contents: vec![items[1]],
+            capacity: 2,
+        };
+        world.delete(items[0]);
+        world.insert((), vec![(name("Spade"),)]);
+        map.set_type(Point::new(3, 2), TileType::Floor);
+        map.light[5] = [0.5, 0.5, 0.5];
+
+        let delta = replicator.delta(&world, &map).unwrap();
+        assert_eq!(delta.changed.len(), 2);
+        assert_eq!(delta.removed.len(), 1);
+        match &delta.map {
+            Some(MapUpdate::Tiles(tiles)) => assert_eq!(tiles.len(), 2),
+            other => panic!("expected just the changed tiles, got {:?}", other),
+        }
+        replica.apply_delta(delta);
+        assert_eq!(contents(replica.world()), contents(&world));
+        assert_eq!(
+            *replica.resources().get::<Map>().unwrap(),
+            Replicator::capture_map(&map)
+        );
+        assert!(replicator.delta(&world, &map).is_none());
+
+        let next_level = Map::new((6, 4), 2);
+        let update = replicator.delta(&world, &next_level).unwrap().map;
+        match update {
+            Some(MapUpdate::Whole(sent)) => assert_eq!(sent.depth, 2),
+            other => panic!("expected the whole new level, got {:?}", other),
+        }
+    }
+}
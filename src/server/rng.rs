@@ -0,0 +1,83 @@
+use rand::{Error, Rng, RngCore, SeedableRng};
+use rand_pcg::Pcg32;
+
+/// The only source of randomness on the server. Everything that rolls dice (map
+/// builders, systems) goes through this so a session can be replayed from its seed.
+///
+/// PCG is used rather than `StdRng` because its output is fixed for a given seed across
+/// rand releases and its state can be saved as is.
+pub struct RandomNumberGenerator {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl RandomNumberGenerator {
+    pub fn seeded(seed: u64) -> Self {
+        RandomNumberGenerator {
+            seed,
+            rng: Pcg32::seed_from_u64(seed),
+        }
+    }
+
+    pub fn from_entropy() -> Self {
+        Self::seeded(rand::thread_rng().gen())
+    }
+
+    /// The seed the session was started with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// A copy of the generator's current state, for saving. Taking it doesn't advance
+    /// the generator, so a session rolls the same numbers whether it saves or not.
+    pub fn state(&self) -> Pcg32 {
+        self.rng.clone()
+    }
+
+    /// Picks up from a state returned by `state`.
+    pub fn restore(seed: u64, state: Pcg32) -> Self {
+        RandomNumberGenerator { seed, rng: state }
+    }
+}
+
+impl RngCore for RandomNumberGenerator {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RandomNumberGenerator;
+    use rand::RngCore;
+
+    #[test]
+    fn test_saving_leaves_the_stream_alone() {
+        let mut unsaved = RandomNumberGenerator::seeded(11);
+        let mut saved = RandomNumberGenerator::seeded(11);
+        for _ in 0..10 {
+            assert_eq!(saved.next_u64(), unsaved.next_u64());
+        }
+
+        let raw = serde_json::to_string(&saved.state()).unwrap();
+        let mut loaded = RandomNumberGenerator::restore(11, serde_json::from_str(&raw).unwrap());
+        for _ in 0..100 {
+            let expected = unsaved.next_u64();
+            assert_eq!(saved.next_u64(), expected);
+            assert_eq!(loaded.next_u64(), expected);
+        }
+        assert_eq!(loaded.seed(), 11);
+    }
+}
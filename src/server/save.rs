@@ -0,0 +1,320 @@
+use crate::component::{
+    ActiveTurn, Customer, CustomerSpawner, DisplayCabinet, Energy, Inventory, LightSource, Name,
+    Player, Position, Priority, Renderable, Speed, TileBlocker, Value, Viewshed, Wallet,
+};
+use crate::error::{Error, Result};
+use crate::map::Map;
+use crate::server::gamestate::RunState;
+use crate::server::levels::LevelManager;
+use crate::server::map_builders::BuiltMap;
+use legion::prelude::*;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Bumped whenever `SaveGame` changes shape. Older saves are rejected rather than
+/// half-loaded.
+pub const SAVE_VERSION: u32 = 13;
+
+const APP_NAME: &str = "four-am";
+const PROFILE: &str = "session";
+
+/// Entity references are stored as indices into `SaveGame::entities` and turned back
+/// into fresh entities on load.
+pub type SavedEntityRef = usize;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedInventory {
+    pub contents: Vec<SavedEntityRef>,
+    pub capacity: u8,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedEntity {
+    pub name: Name,
+    pub position: Option<Position>,
+    pub renderable: Option<Renderable>,
+    pub priority: Option<Priority>,
+    pub speed: Option<Speed>,
+    pub energy: Option<Energy>,
+    pub active_turn: Option<ActiveTurn>,
+    pub inventory: Option<SavedInventory>,
+    pub value: Option<Value>,
+    pub wallet: Option<Wallet>,
+    pub customer: Option<Customer>,
+    pub customer_spawner: Option<CustomerSpawner>,
+    pub viewshed: Option<Viewshed>,
+    pub light: Option<LightSource>,
+    pub tile_blocker: bool,
+    pub player: bool,
+    pub display_cabinet: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedRng {
+    pub seed: u64,
+    pub state: Pcg32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedMapState {
+    pub mapgen_index: usize,
+    pub mapgen_built_map: BuiltMap,
+    pub mapgen_frame_ticks: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub entities: Vec<SavedEntity>,
+    pub map: Map,
+    pub levels: LevelManager,
+    pub run_state: RunState,
+    pub ticks: u64,
+    pub rng: SavedRng,
+    pub map_state: SavedMapState,
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+impl SaveGame {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|err| Error::Save(err.to_string()))
+    }
+
+    pub fn from_json(raw: &str) -> Result<SaveGame> {
+        let header: SaveHeader =
+            serde_json::from_str(raw).map_err(|err| Error::Save(err.to_string()))?;
+        if header.version != SAVE_VERSION {
+            return Err(Error::Save(format!(
+                "save version {} is not supported (expected {})",
+                header.version, SAVE_VERSION
+            )));
+        }
+        serde_json::from_str(raw).map_err(|err| Error::Save(err.to_string()))
+    }
+
+    /// Writes the save wherever quicksilver keeps app data (local storage on the web).
+    pub fn store(&self) -> Result<()> {
+        let raw = self.to_json()?;
+        quicksilver::saving::save(quicksilver::saving::Location::Data, APP_NAME, PROFILE, &raw)
+            .map_err(|err| Error::Save(format!("{:?}", err)))
+    }
+
+    pub fn restore() -> Result<SaveGame> {
+        let raw: String =
+            quicksilver::saving::load(quicksilver::saving::Location::Data, APP_NAME, PROFILE)
+                .map_err(|err| Error::Save(format!("{:?}", err)))?;
+        SaveGame::from_json(&raw)
+    }
+}
+
+/// Captures every named entity in `world`. The returned lookup lets callers translate
+/// other entity references into the same indices.
+pub fn save_entities(world: &World) -> (Vec<SavedEntity>, HashMap<Entity, SavedEntityRef>) {
+    let query = <Read<Name>>::query();
+    let named = query
+        .iter_entities(world)
+        .map(|(entity, name)| (entity, (*name).clone()))
+        .collect::<Vec<_>>();
+    save_named(world, named)
+}
+
+/// Saves and removes every named entity except those in `keep`. References to kept
+/// entities are dropped from the saved inventories.
+pub fn park_entities(world: &mut World, keep: &[Entity]) -> Vec<SavedEntity> {
+    let query = <Read<Name>>::query();
+    let named = query
+        .iter_entities(world)
+        .filter(|(entity, _)| !keep.contains(entity))
+        .map(|(entity, name)| (entity, (*name).clone()))
+        .collect::<Vec<_>>();
+    let parked = named.iter().map(|(entity, _)| *entity).collect::<Vec<_>>();
+    let (saved, _) = save_named(world, named);
+    for entity in parked {
+        world.delete(entity);
+    }
+    saved
+}
+
+fn save_named(
+    world: &World,
+    named: Vec<(Entity, Name)>,
+) -> (Vec<SavedEntity>, HashMap<Entity, SavedEntityRef>) {
+    let lookup = named
+        .iter()
+        .enumerate()
+        .map(|(index, (entity, _))| (*entity, index))
+        .collect::<HashMap<_, _>>();
+
+    let entities = named
+        .into_iter()
+        .map(|(entity, name)| SavedEntity {
+            name,
+            position: world.get_component::<Position>(entity).map(|c| *c),
+            renderable: world.get_component::<Renderable>(entity).map(|c| *c),
+            priority: world.get_component::<Priority>(entity).map(|c| *c),
+            speed: world.get_component::<Speed>(entity).map(|c| *c),
+            energy: world.get_component::<Energy>(entity).map(|c| *c),
+            active_turn: world.get_component::<ActiveTurn>(entity).map(|c| *c),
+            inventory: world
+                .get_component::<Inventory>(entity)
+                .map(|inventory| SavedInventory {
+                    contents: inventory
+                        .contents
+                        .iter()
+                        .filter_map(|item| lookup.get(item).cloned())
+                        .collect(),
+                    capacity: inventory.capacity,
+                }),
+            value: world.get_component::<Value>(entity).map(|c| *c),
+            wallet: world.get_component::<Wallet>(entity).map(|c| *c),
+            customer: world.get_component::<Customer>(entity).map(|c| *c),
+            customer_spawner: world
+                .get_component::<CustomerSpawner>(entity)
+                .map(|c| (*c).clone()),
+            viewshed: world
+                .get_component::<Viewshed>(entity)
+                .map(|c| (*c).clone()),
+            light: world.get_component::<LightSource>(entity).map(|c| *c),
+            tile_blocker: world.get_component::<TileBlocker>(entity).is_some(),
+            player: world.get_tag::<Player>(entity).is_some(),
+            display_cabinet: world.get_tag::<DisplayCabinet>(entity).is_some(),
+        })
+        .collect();
+    (entities, lookup)
+}
+
+/// Recreates saved entities in `world`, returning the new entity for every saved index.
+pub fn load_entities(world: &mut World, saved: &[SavedEntity]) -> Vec<Entity> {
+    let entities = saved
+        .iter()
+        .map(|entity| world.insert((), vec![(entity.name.clone(),)])[0])
+        .collect::<Vec<_>>();
+
+    let mut command_buffer = CommandBuffer::new(world);
+    for (entity, saved) in entities.iter().cloned().zip(saved) {
+        if let Some(position) = saved.position {
+            command_buffer.add_component(entity, position);
+        }
+        if let Some(renderable) = saved.renderable {
+            command_buffer.add_component(entity, renderable);
+        }
+        if let Some(priority) = saved.priority {
+            command_buffer.add_component(entity, priority);
+        }
+        if let Some(speed) = saved.speed {
+            command_buffer.add_component(entity, speed);
+        }
+        if let Some(energy) = saved.energy {
+            command_buffer.add_component(entity, energy);
+        }
+        if let Some(active_turn) = saved.active_turn {
+            command_buffer.add_component(entity, active_turn);
+        }
+        if let Some(inventory) = &saved.inventory {
+            let contents = inventory
+                .contents
+                .iter()
+                .filter_map(|index| entities.get(*index).cloned())
+                .collect();
+            command_buffer.add_component(
+                entity,
+                Inventory {
+                    contents,
+                    capacity: inventory.capacity,
+                },
+            );
+        }
+        if let Some(value) = saved.value {
+            command_buffer.add_component(entity, value);
+        }
+        if let Some(wallet) = saved.wallet {
+            command_buffer.add_component(entity, wallet);
+        }
+        if let Some(customer) = saved.customer {
+            command_buffer.add_component(entity, customer);
+        }
+        if let Some(spawner) = &saved.customer_spawner {
+            command_buffer.add_component(entity, spawner.clone());
+        }
+        if let Some(viewshed) = &saved.viewshed {
+            command_buffer.add_component(entity, viewshed.clone());
+        }
+        if let Some(light) = saved.light {
+            command_buffer.add_component(entity, light);
+        }
+        if saved.tile_blocker {
+            command_buffer.add_component(entity, TileBlocker);
+        }
+        if saved.player {
+            command_buffer.add_tag(entity, Player);
+        }
+        if saved.display_cabinet {
+            command_buffer.add_tag(entity, DisplayCabinet);
+        }
+    }
+    command_buffer.write(world);
+    entities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_entities, save_entities};
+    use crate::component::{Inventory, Name, Player};
+    use legion::prelude::*;
+
+    fn name(world: &World, entity: Entity) -> String {
+        world.get_component::<Name>(entity).unwrap().name.clone()
+    }
+
+    #[test]
+    fn test_inventory_references_survive_reload() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let items = world
+            .insert(
+                (),
+                vec![
+                    (Name {
+                        name: "Heart".to_string(),
+                    },),
+                    (Name {
+                        name: "Club".to_string(),
+                    },),
+                ],
+            )
+            .to_vec();
+        let player = world.insert(
+            (),
+            vec![(Name {
+                name: "Player".to_string(),
+            },)],
+        )[0];
+        let mut command_buffer = CommandBuffer::new(&world);
+        command_buffer.add_component(
+            player,
+            Inventory {
+                contents: vec![items[1], items[0]],
+                capacity: 3,
+            },
+        );
+        command_buffer.add_tag(player, Player);
+        command_buffer.write(&mut world);
+
+        let (saved, _) = save_entities(&world);
+        let mut reloaded = universe.create_world();
+        load_entities(&mut reloaded, &saved);
+
+        let query = <Read<Inventory>>::query().filter(tag::<Player>());
+        let contents = query.iter(&reloaded).next().unwrap().contents.clone();
+        let names = contents
+            .iter()
+            .map(|item| name(&reloaded, *item))
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["Club".to_string(), "Heart".to_string()]);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::error::{Error, Result};
+use crate::network::protocol::Request;
+
+// Scripts are plain text with one request per line. Directions are relative to the player,
+// so `take 1 0` takes from whatever is standing to the east. Blank lines and lines starting
+// with '#' are ignored:
+//   move <dx> <dy>
+//   wait
+//   interact <dx> <dy>
+//   take <dx> <dy>
+//   put <dx> <dy> <inventory slot>
+//   buy <dx> <dy>
+//   sell <dx> <dy> <inventory slot>
+//   descend
+//   ascend
+pub fn parse(source: &str) -> Result<Vec<Request>> {
+    let mut commands = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        commands.push(parse_line(line).map_err(|reason| {
+            Error::Script(format!("line {}: {} ({:?})", index + 1, reason, line))
+        })?);
+    }
+    Ok(commands)
+}
+
+fn parse_line(line: &str) -> std::result::Result<Request, String> {
+    let mut parts = line.split_whitespace();
+    let name = parts.next().unwrap_or("");
+    let args = parts
+        .map(|part| {
+            part.parse::<i32>()
+                .map_err(|_| format!("bad argument {}", part))
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let request = match (name, args.as_slice()) {
+        ("move", [dx, dy]) => Request::Move { dx: *dx, dy: *dy },
+        ("wait", []) => Request::Wait,
+        ("interact", [dx, dy]) => Request::Interact { dx: *dx, dy: *dy },
+        ("take", [dx, dy]) => Request::Take { dx: *dx, dy: *dy },
+        ("buy", [dx, dy]) => Request::Buy { dx: *dx, dy: *dy },
+        ("descend", []) => Request::Descend,
+        ("ascend", []) => Request::Ascend,
+        ("put", [dx, dy, slot]) if *slot >= 0 => Request::Put {
+            dx: *dx,
+            dy: *dy,
+            slot: *slot as usize,
+        },
+        ("sell", [dx, dy, slot]) if *slot >= 0 => Request::Sell {
+            dx: *dx,
+            dy: *dy,
+            slot: *slot as usize,
+        },
+        _ => return Err(format!("unknown command {:?}", name)),
+    };
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::network::protocol::Request;
+
+    #[test]
+    fn test_parse() {
+        let script =
+            "# walk to the case\nmove 1 0\n\nwait\ntake 1 0\nput 0 1 2\nbuy 1 0\nsell 1 0 0\n";
+        let commands = parse(script).unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                Request::Move { dx: 1, dy: 0 },
+                Request::Wait,
+                Request::Take { dx: 1, dy: 0 },
+                Request::Put {
+                    dx: 0,
+                    dy: 1,
+                    slot: 2
+                },
+                Request::Buy { dx: 1, dy: 0 },
+                Request::Sell {
+                    dx: 1,
+                    dy: 0,
+                    slot: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown() {
+        assert!(parse("move 1").is_err());
+        assert!(parse("jump 1 1").is_err());
+        assert!(parse("put 1 1 -1").is_err());
+    }
+}
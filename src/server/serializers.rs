@@ -9,11 +9,18 @@ pub struct Data {
 #[derive(Deserialize, Debug, Clone)]
 pub struct EntityBuilder {
     id: String,
-    renderable: Renderable,
+    renderable: Option<Renderable>,
     name: String,
     priority: Option<Priority>,
+    speed: Option<Speed>,
     display_cabinet: Option<bool>,
     inventory: Option<Inventory>,
+    value: Option<Value>,
+    wallet: Option<Wallet>,
+    customer: Option<Customer>,
+    customer_spawner: Option<CustomerSpawner>,
+    viewshed: Option<Viewshed>,
+    light: Option<LightSource>,
 }
 #[derive(Deserialize, Debug, Clone)]
 pub struct Inventory {
@@ -39,14 +46,53 @@ pub struct Priority {
     pub value: u8,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct Speed {
+    pub value: i32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Value {
+    pub price: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Wallet {
+    pub coins: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Customer {
+    pub visits: u8,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Viewshed {
+    pub range: i32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LightSource {
+    pub radius: i32,
+    pub color: String,
+    pub intensity: f32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CustomerSpawner {
+    pub customer: String,
+    pub max_customers: u8,
+}
+
 
 pub mod entity_factory {
     use std::collections::HashMap;
     use super::{Data, EntityBuilder};
-    use quicksilver::{graphics::Color, load_file};
+    use quicksilver::load_file;
     use serde_json::from_str;
-    use crate::{component, geom::Point, frontend::glyph::Glyph};
+    use crate::{color::Color, component, geom::Point, frontend::glyph::Glyph};
     use legion::prelude::{Entity, CommandBuffer};
+    use std::path::Path;
 
     pub struct EntityFactory {
         registry: HashMap<String, EntityBuilder>
@@ -57,6 +103,15 @@ pub mod entity_factory {
         pub async fn load() -> Self {
             let file_contents = load_file("data/entities.json").await.expect("Couldn't find entity factory file");
             let raw_string = std::str::from_utf8(&file_contents).expect("Couldn't get raw string from factory file");
+            Self::from_json(raw_string)
+        }
+
+        pub fn load_from_path(path: impl AsRef<Path>) -> crate::error::Result<Self> {
+            let raw_string = std::fs::read_to_string(path)?;
+            Ok(Self::from_json(&raw_string))
+        }
+
+        pub fn from_json(raw_string: &str) -> Self {
             let mut data: Data = from_str(raw_string).expect("Invalid entity factory file");
             let mut registry = HashMap::new();
             for factory in data.builder.drain(..) {
@@ -78,14 +133,6 @@ pub mod entity_factory {
             let options = self.registry.get(id).expect(&format!("Could not find {:?}", id));
             let builder = buffer.start_entity();
             let builder = builder
-                .with_component(component::Renderable {
-                    glyph: Glyph {
-                        ch: options.renderable.glyph.ch,
-                        foreground: Self::deserialize_color(options.renderable.glyph.foreground.clone()),
-                        background: Self::deserialize_color(options.renderable.glyph.background.clone()),
-                        render_order: options.renderable.glyph.render_order,
-                    },
-                })
                 .with_component(component::Name {
                     name: options.name.clone(),
                 })
@@ -93,6 +140,16 @@ pub mod entity_factory {
 
             let entity = builder.build();
 
+            if let Some(renderable) = &options.renderable {
+                buffer.add_component(entity, component::Renderable {
+                    glyph: Glyph {
+                        ch: renderable.glyph.ch,
+                        foreground: Self::deserialize_color(renderable.glyph.foreground.clone()),
+                        background: Self::deserialize_color(renderable.glyph.background.clone()),
+                        render_order: renderable.glyph.render_order,
+                    },
+                });
+            }
             if let Some(position) = &position {
                 buffer.add_component(entity, component::Position {
                     x: position.x,
@@ -102,6 +159,43 @@ pub mod entity_factory {
             if let Some(priority) = &options.priority {
                 buffer.add_component(entity, component::Priority{value: priority.value})
             }
+            if let Some(speed) = &options.speed {
+                buffer.add_component(entity, component::Speed{value: speed.value});
+                buffer.add_component(entity, component::Energy{value: 0});
+            }
+
+            if let Some(value) = &options.value {
+                buffer.add_component(entity, component::Value{price: value.price});
+            }
+            if let Some(wallet) = &options.wallet {
+                buffer.add_component(entity, component::Wallet{coins: wallet.coins});
+            }
+            if let Some(customer) = &options.customer {
+                buffer.add_component(entity, component::Customer {
+                    state: component::CustomerState::Browsing { target: None },
+                    visits_left: customer.visits,
+                });
+            }
+            if let Some(spawner) = &options.customer_spawner {
+                buffer.add_component(entity, component::CustomerSpawner {
+                    customer: spawner.customer.clone(),
+                    max_customers: spawner.max_customers,
+                });
+            }
+            if let Some(viewshed) = &options.viewshed {
+                buffer.add_component(entity, component::Viewshed {
+                    range: viewshed.range,
+                    visible_tiles: vec![],
+                    dirty: true,
+                });
+            }
+            if let Some(light) = &options.light {
+                buffer.add_component(entity, component::LightSource {
+                    radius: light.radius,
+                    color: Color::from_hex(&light.color),
+                    intensity: light.intensity,
+                });
+            }
 
             let mut has_inventory = false;
             if let Some(inventory) = &options.inventory {
@@ -1,18 +1,31 @@
 use crate::component::TurnState;
+use crate::error::Result;
+use crate::geom::Point;
 use crate::message::Message;
+use crate::network::protocol::Request;
 use crate::component;
 
-use crate::map::Map;
+use crate::map::{Map, TileType};
+use crate::server::action::{Action, ActionResult, ActionResults, Intent};
 use crate::server::gamestate::RunState;
+use crate::server::levels::{self, LevelManager, LevelTransition};
 use crate::server::map_builders::factories::drunk_builder;
+use crate::server::map_builders::prefab::Prefabs;
+use crate::server::map_builders::spawner::SpawnTables;
 use crate::server::map_builders::BuiltMap;
+use crate::server::replay::{self, Replay, CHECKPOINT_INTERVAL};
+use crate::server::rng::RandomNumberGenerator;
+use crate::server::save::{self, SaveGame, SavedMapState, SavedRng, SAVE_VERSION};
+use crate::server::systems::customer_system::{customer_spawn_system, customer_system};
 use crate::server::systems::index_system::index_system;
-use crate::server::systems::turn_system::{turn_system, PendingMoves};
+use crate::server::systems::lighting_system::lighting_system;
+use crate::server::systems::action_system::action_system;
+use crate::server::systems::turn_system::turn_system;
+use crate::server::systems::viewshed_system::viewshed_system;
+use crate::tiles::TileDefinitions;
 
-use instant::Instant;
 use legion::prelude::*;
-use quicksilver::graphics::Color;
-use std::cmp::{max, min};
+use std::path::Path;
 use super::{map_builders::factories::shop_builder, serializers::{entity_factory}};
 
 pub struct Server {
@@ -22,14 +35,31 @@ pub struct Server {
     schedule: Schedule,
     run_state: RunState,
     map_state: MapState,
-    factory: entity_factory::EntityFactory
+    levels: LevelManager,
+    ticks: u64,
+    recording: Option<Replay>,
 }
+#[derive(Clone, Default)]
+pub struct ServerSettings {
+    /// Seed for every random roll on the server. A fresh one is picked when unset.
+    pub seed: Option<u64>,
+    /// Play back how the shop was built, step by step, before the game starts.
+    pub show_map_generation: bool,
+}
+
+/// How many ticks each step of map generation stays on screen for. Counting ticks rather
+/// than time keeps playback the same from run to run; at the client's rate of a tick
+/// every half millisecond it comes to 0.3 seconds a step.
+const MAPGEN_FRAME_TICKS: u32 = 600;
+
 pub struct MapState {
     mapgen_index: usize,
     mapgen_built_map: BuiltMap,
-    mapgen_timer: Instant,
+    /// Ticks the current step of map generation has been on screen for.
+    mapgen_frame_ticks: u32,
 }
 
+#[derive(Default)]
 pub struct MessageQueue {
     messages: Vec<Message>,
 }
@@ -48,36 +78,83 @@ impl Server {
         let world = universe.create_world();
 
         let mut resources = Resources::default();
-        let turn = PendingMoves::new();
 
         let message_queue = MessageQueue { messages: vec![] };
-        resources.insert(turn);
-
         resources.insert(message_queue);
+        resources.insert(ActionResults::default());
+        resources.insert(LevelTransition::default());
         (universe, world, resources)
     }
 
-    pub async fn new() -> Self {
-        let (universe, world, mut resources) = Self::setup_ecs();
-        let mut rng = rand::thread_rng();
-        let built_map = shop_builder((20, 20).into(), &mut rng);
-        let BuiltMap {
-            spawn_list: _,
-            map,
-            starting_position: _,
-            rooms: _,
-            history,
-            with_history,
-        } = &built_map;
+    pub async fn new(settings: ServerSettings) -> Self {
         let factory = entity_factory::EntityFactory::load().await;
-        if *with_history {
-            resources.insert(history[0].clone())
-        } else {
-            resources.insert(map.clone());
-        }
+        let tiles = TileDefinitions::load().await;
+        let prefabs = Prefabs::load().await;
+        let spawn_tables = SpawnTables::load().await;
+        Self::with_data(factory, tiles, prefabs, spawn_tables, settings)
+    }
+
+    /// Builds a server without going through quicksilver's asset loader, reading the
+    /// entity, tile, prefab and spawn table definitions from `data_dir` (usually
+    /// `static/data`).
+    pub fn load_from_path(data_dir: impl AsRef<Path>, settings: ServerSettings) -> Result<Self> {
+        let factory =
+            entity_factory::EntityFactory::load_from_path(data_dir.as_ref().join("entities.json"))?;
+        let tiles = TileDefinitions::load_from_path(data_dir.as_ref().join("tiles.json"))?;
+        let prefabs = Prefabs::load_from_path(data_dir.as_ref().join("prefabs.txt"))?;
+        let spawn_tables =
+            SpawnTables::load_from_path(data_dir.as_ref().join("spawn_tables.json"))?;
+        Ok(Self::with_data(
+            factory,
+            tiles,
+            prefabs,
+            spawn_tables,
+            settings,
+        ))
+    }
+
+    pub fn with_data(
+        factory: entity_factory::EntityFactory,
+        tiles: TileDefinitions,
+        prefabs: Prefabs,
+        spawn_tables: SpawnTables,
+        settings: ServerSettings,
+    ) -> Self {
+        let (universe, world, mut resources) = Self::setup_ecs();
+        let mut rng = match settings.seed {
+            Some(seed) => RandomNumberGenerator::seeded(seed),
+            None => RandomNumberGenerator::from_entropy(),
+        };
+        let built_map = shop_builder(
+            (20, 20).into(),
+            &prefabs,
+            settings.show_map_generation,
+            &mut rng,
+        );
+        let BuiltMap { map, history, .. } = &built_map;
+        let run_state = match history.first() {
+            Some(first) => {
+                resources.insert(first.clone());
+                RunState::MapGeneration
+            }
+            None => {
+                resources.insert(map.clone());
+                RunState::Initializing
+            }
+        };
+        resources.insert(rng);
+        resources.insert(factory);
+        resources.insert(tiles);
+        resources.insert(prefabs);
+        resources.insert(spawn_tables);
 
         let schedule = Schedule::builder()
             .add_system(index_system())
+            .add_system(customer_spawn_system())
+            .add_system(customer_system())
+            .add_system(action_system())
+            .add_system(viewshed_system())
+            .add_system(lighting_system())
             .add_system(turn_system())
             .build();
 
@@ -86,17 +163,20 @@ impl Server {
             resources,
             schedule,
             universe,
-            run_state: RunState::Initializing,
+            run_state,
             map_state: MapState {
                 mapgen_index: 0,
                 mapgen_built_map: built_map,
-                mapgen_timer: Instant::now(),
+                mapgen_frame_ticks: 0,
             },
-            factory
+            levels: LevelManager::new(),
+            ticks: 0,
+            recording: None,
         }
     }
 
     fn insert_entities(&mut self) {
+        let factory = self.resources.get::<entity_factory::EntityFactory>().unwrap();
         let mut command_buffer = CommandBuffer::new(&self.world);
         let position = self
             .map_state
@@ -104,14 +184,18 @@ impl Server {
             .starting_position
             .unwrap()
             .clone();
-        let player = self.factory.build("player", Some(position), &mut command_buffer);
+        let player = factory.build("player", Some(position), &mut command_buffer);
         command_buffer.add_tag(player, component::Player);
-        let entity = self.factory.build("display", Some((position.x + 1, position.y).into()), &mut command_buffer);
-        self.factory.build("display", Some((position.x + 1, position.y + 1).into()), &mut command_buffer);
-        self.factory.build("display", Some((position.x + 1, position.y + 2).into()), &mut command_buffer);
-        let love = self.factory.build("love", None, &mut command_buffer);
+        factory.build("customer_spawner", None, &mut command_buffer);
+        let built_map = &self.map_state.mapgen_built_map;
+        spawn_entities(
+            &factory,
+            &built_map.map,
+            &built_map.spawn_list,
+            &mut command_buffer,
+        );
+        std::mem::drop(factory);
         command_buffer.write(&mut self.world);
-        self.world.get_component_mut::<component::Inventory>(entity).unwrap().contents.push(love);
     }
 
     pub fn messages(&mut self) -> Vec<Message> {
@@ -130,108 +214,360 @@ impl Server {
                 let resources = &mut self.resources;
                 let schedule = &mut self.schedule;
                 schedule.execute(world, resources);
+                let depth = resources.get_mut::<LevelTransition>().unwrap().depth.take();
+                if let Some(depth) = depth {
+                    self.change_level(depth);
+                }
+            }
+            RunState::MapGeneration => {
+                self.show_next_mapgen_frame();
+                // Nothing has happened in the game yet, so this doesn't count as a tick.
+                return;
             }
             RunState::Initializing => {
                 let resources = &mut self.resources;
                 let mut map = resources.get_mut::<Map>().unwrap();
-                map.refresh_blocked();
+                map.refresh_blocked(&resources.get::<TileDefinitions>().unwrap());
                 std::mem::drop(map);
                 self.insert_entities();
                 self.run_state = RunState::Running;
             }
-            _ => panic!("Unhandled runstate!"),
+            // Nothing moves while the game is paused, and no time passes either.
+            RunState::Paused => return,
+        }
+
+        self.ticks += 1;
+        if self.recording.is_some() && self.ticks % CHECKPOINT_INTERVAL == 0 {
+            let hash = self.state_hash();
+            self.recording.as_mut().unwrap().checkpoint(self.ticks, hash);
         }
     }
 
-    pub fn get_player(&self) -> Entity {
-        let query = <(Read<component::Position>)>::query().filter(tag::<component::Player>());
-        query.iter_entities(&self.world).next().unwrap().0
+    /// Swaps the map for the next snapshot of it being built once the current one has been
+    /// up long enough, moving on to the game proper after the last.
+    fn show_next_mapgen_frame(&mut self) {
+        let map_state = &mut self.map_state;
+        map_state.mapgen_frame_ticks += 1;
+        if map_state.mapgen_frame_ticks < MAPGEN_FRAME_TICKS {
+            return;
+        }
+        map_state.mapgen_frame_ticks = 0;
+        map_state.mapgen_index += 1;
+        let built_map = &map_state.mapgen_built_map;
+        match built_map.history.get(map_state.mapgen_index) {
+            Some(frame) => self.resources.insert(frame.clone()),
+            None => {
+                self.resources.insert(built_map.map.clone());
+                self.run_state = RunState::Initializing;
+            }
+        }
     }
 
-    pub fn get_player_inventory(&self) -> Vec<Entity> {
-        let query = <(Read<component::Inventory>)>::query().filter(tag::<component::Player>());
-        query.iter(&self.world).next().unwrap().as_ref().contents.clone()
+    /// Number of times `tick` has run since the session started.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
     }
 
-    pub fn try_interact(&mut self, entity: Entity) -> bool {
-        let mut renderable = self.world.get_component_mut::<component::Renderable>(entity).unwrap();
-        if renderable.glyph.foreground != Some(Color::RED) {
-            renderable.glyph.foreground = Some(Color::RED);
-        } else {
-            renderable.glyph.foreground = Some(Color::GREEN);
-        }
-        true
+    pub fn state_hash(&self) -> u64 {
+        let map = self.resources.get::<Map>().unwrap();
+        replay::world_hash(&self.world, &map, self.ticks)
     }
 
-    pub fn try_player_put(&mut self, entity: Entity, player_inv: Entity) -> bool {
-        let player_entity = self.get_player();
-        let player_inventory = self.world.get_component_mut::<component::Inventory>(player_entity).unwrap().contents.remove_item(&player_inv);
-        self.world.get_component_mut::<component::Inventory>(entity).unwrap().contents.push(player_inv);
-        true
+    /// Starts logging every player request so the session can be replayed from its seed.
+    /// Only fresh sessions can be recorded.
+    pub fn start_recording(&mut self) {
+        assert_eq!(self.ticks, 0, "recording must start before the first tick");
+        self.recording = Some(Replay::new(self.seed()));
     }
 
-    pub fn try_player_take(&mut self, entity: Entity) -> bool {
-        let contents = {
-            let mut inv = self.world.get_component_mut::<component::Inventory>(entity).unwrap();
-            inv.as_mut().contents.pop()
+    pub fn recording(&self) -> Option<&Replay> {
+        self.recording.as_ref()
+    }
+
+    /// Stops recording, storing a last checkpoint for the current tick.
+    pub fn finish_recording(&mut self) -> Option<Replay> {
+        let hash = self.state_hash();
+        let mut recording = self.recording.take()?;
+        recording.checkpoint(self.ticks, hash);
+        Some(recording)
+    }
+
+    /// Moves the player and everything they carry to `depth`, parking the level they
+    /// left so it can be picked up again where it was.
+    fn change_level(&mut self, depth: i32) {
+        let player = self.get_player();
+        let mut travellers = self.get_player_inventory();
+        travellers.push(player);
+
+        let current = self.resources.get::<Map>().unwrap().clone();
+        let descending = depth > current.depth;
+        let mut parked = save::park_entities(&mut self.world, &travellers);
+        // The scheduler may already have handed the next turn to someone being parked.
+        for entity in parked.iter_mut() {
+            entity.active_turn = None;
+        }
+        self.levels.park(current, parked);
+
+        let mut map = match self.levels.unpark(depth) {
+            Some(level) => {
+                save::load_entities(&mut self.world, &level.entities);
+                level.map
+            }
+            None => {
+                let mut rng = self.resources.get_mut::<RandomNumberGenerator>().unwrap();
+                let tiles = self.resources.get::<TileDefinitions>().unwrap();
+                let prefabs = self.resources.get::<Prefabs>().unwrap();
+                let spawn_tables = self.resources.get::<SpawnTables>().unwrap();
+                let built_map = levels::generate(depth, &tiles, &prefabs, &spawn_tables, &mut rng);
+                let factory = self
+                    .resources
+                    .get::<entity_factory::EntityFactory>()
+                    .unwrap();
+                let mut command_buffer = CommandBuffer::new(&self.world);
+                spawn_entities(
+                    &factory,
+                    &built_map.map,
+                    &built_map.spawn_list,
+                    &mut command_buffer,
+                );
+                command_buffer.write(&mut self.world);
+                built_map.map
+            }
         };
-        if let Some(contents) = contents {
-            let player_entity = self.get_player();
-            let player_inventory = self.world.get_component_mut::<component::Inventory>(player_entity).unwrap().contents.push(contents.clone());
-            let world = &mut self.world;
-            let resources = &mut self.resources;
-            let name = world.get_component_mut::<component::Name>(contents).unwrap();
-            let mut message_queue = resources.get_mut::<MessageQueue>().unwrap();
-            message_queue.push(Message::GameEvent(
-                format!("You took {:?}", name.name),
-                Some(Color::GREEN),
-                None,
-            ));
-            true
+        // Arrive on the stairs leading back the way the player came.
+        let arrival = if descending {
+            map.find(TileType::UpStairs)
         } else {
-            false
+            map.find(TileType::DownStairs)
+        };
+        if let Some(arrival) = arrival {
+            let mut position = self
+                .world
+                .get_component_mut::<component::Position>(player)
+                .unwrap();
+            position.x = arrival.x;
+            position.y = arrival.y;
+        }
+        let viewshed = self.world.get_component_mut::<component::Viewshed>(player);
+        if let Some(mut viewshed) = viewshed {
+            viewshed.dirty = true;
         }
+        map.refresh_blocked(&self.resources.get::<TileDefinitions>().unwrap());
+        map.refresh_content();
+        self.resources.insert(map);
+
+        let text = if descending {
+            format!("You descend to depth {}.", depth)
+        } else {
+            format!("You climb up to depth {}.", depth)
+        };
+        self.resources
+            .get_mut::<MessageQueue>()
+            .unwrap()
+            .push(Message::GameEvent(text, None, None));
+    }
+
+    pub fn get_player(&self) -> Entity {
+        let query = <(Read<component::Position>)>::query().filter(tag::<component::Player>());
+        query.iter_entities(&self.world).next().unwrap().0
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.resources.get::<RandomNumberGenerator>().unwrap().seed()
     }
 
-    pub fn try_move_player(&mut self, delta_x: i32, delta_y: i32) -> bool {
+    pub fn get_player_position(&self) -> Point {
+        let player = self.get_player();
+        (*self.world.get_component::<component::Position>(player).unwrap()).into()
+    }
+
+    /// Whether the player is waiting on a new intent.
+    pub fn is_player_turn(&self) -> bool {
+        let query = <(Read<component::ActiveTurn>)>::query().filter(tag::<component::Player>());
+        query
+            .iter_entities(&self.world)
+            .any(|(entity, turn)| {
+                turn.state == TurnState::PENDING
+                    && self.world.get_component::<Intent>(entity).is_none()
+            })
+    }
+
+    pub fn entity_at(&self, point: Point) -> Option<Entity> {
+        self.resources.get::<Map>().unwrap().content_at(point)
+    }
+
+    /// Queues a request as the player's intent. The action itself is carried out by the
+    /// action system on the player's turn; see `action_results` for how it went.
+    pub fn handle_request(&mut self, request: Request) -> bool {
+        let action = match Action::from_request(request) {
+            Some(action) => action,
+            // Sessions are saved and loaded by whoever is hosting the server.
+            None => return false,
+        };
         if self.run_state != RunState::Running {
             return false;
         }
-        let world = &mut self.world;
-        let resources = &mut self.resources;
-        let mut message_queue = resources.get_mut::<MessageQueue>().unwrap();
-        let map = resources.get_mut::<Map>().unwrap();
-        let query = <(Write<component::Position>, Write<component::ActiveTurn>)>::query()
-            .filter(tag::<component::Player>());
-
-        let mut command_buffer = CommandBuffer::new(&world);
-
-        let mut moved = false;
-        for (entity, (mut pos, mut turn)) in query.iter_entities_mut(world) {
-            let desired_x = min(map.size.x, max(0, pos.x + delta_x));
-            let desired_y = min(map.size.y, max(0, pos.y + delta_y));
-
-            let coord = map.coord_to_index(desired_x, desired_y);
-            if map.blocked[coord] {
-                message_queue.push(Message::GameEvent(
-                    format!("Ouch, you hit a wall!"),
-                    Some(Color::RED),
-                    None,
-                ));
-            } else if let Some(other) = map.tile_content[coord] {
-                if entity != other {
+        if let Some(recording) = &mut self.recording {
+            recording.record(self.ticks, request);
+        }
+        let player = self.get_player();
+        let mut command_buffer = CommandBuffer::new(&self.world);
+        command_buffer.add_component(player, Intent { action });
+        command_buffer.write(&mut self.world);
+        true
+    }
 
-                }
-            } else {
-                pos.x = desired_x;
-                pos.y = desired_y;
-                moved = true;
+    /// Outcomes of the actions carried out since the last call.
+    pub fn action_results(&mut self) -> Vec<ActionResult> {
+        let mut results = self.resources.get_mut::<ActionResults>().unwrap();
+        results.results.drain(..).collect()
+    }
+
+    pub fn save(&self) -> SaveGame {
+        let rng = {
+            let rng = self.resources.get::<RandomNumberGenerator>().unwrap();
+            SavedRng {
+                seed: rng.seed(),
+                state: rng.state(),
             }
-            turn.state = TurnState::DONE;
+        };
+        let (entities, _) = save::save_entities(&self.world);
+        SaveGame {
+            version: SAVE_VERSION,
+            entities,
+            map: self.resources.get::<Map>().unwrap().clone(),
+            levels: self.levels.clone(),
+            run_state: self.run_state,
+            ticks: self.ticks,
+            rng,
+            map_state: SavedMapState {
+                mapgen_index: self.map_state.mapgen_index,
+                mapgen_built_map: self.map_state.mapgen_built_map.clone(),
+                mapgen_frame_ticks: self.map_state.mapgen_frame_ticks,
+            },
         }
+    }
+
+    /// Replaces the current session with `save`. The entity factory and schedule are kept.
+    pub fn load(&mut self, save: SaveGame) {
+        let mut world = self.universe.create_world();
+        save::load_entities(&mut world, &save.entities);
+        self.world = world;
 
-        command_buffer.write(world);
-        moved
+        let mut map = save.map;
+        map.refresh_content();
+        self.resources.insert(map);
+        self.levels = save.levels;
+        self.resources
+            .insert(RandomNumberGenerator::restore(save.rng.seed, save.rng.state));
+        self.resources.get_mut::<MessageQueue>().unwrap().messages.clear();
+        self.resources.insert(ActionResults::default());
+        // Stairs taken just before loading lead nowhere in the loaded game.
+        self.resources.insert(LevelTransition::default());
+
+        // A recording only makes sense from the session's first tick.
+        self.recording = None;
+        self.run_state = save.run_state;
+        self.ticks = save.ticks;
+        self.map_state = MapState {
+            mapgen_index: save.map_state.mapgen_index,
+            mapgen_built_map: save.map_state.mapgen_built_map,
+            mapgen_frame_ticks: save.map_state.mapgen_frame_ticks,
+        };
+    }
+
+    pub fn get_player_inventory(&self) -> Vec<Entity> {
+        let query = <(Read<component::Inventory>)>::query().filter(tag::<component::Player>());
+        query.iter(&self.world).next().unwrap().as_ref().contents.clone()
+    }
+}
+
+/// Builds every entity a map builder asked for, at the tile it asked for it on.
+fn spawn_entities(
+    factory: &entity_factory::EntityFactory,
+    map: &Map,
+    spawn_list: &[(usize, String)],
+    command_buffer: &mut CommandBuffer,
+) {
+    for (index, id) in spawn_list {
+        factory.build(id, Some(map.index_to_point(*index)), command_buffer);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::{Server, ServerSettings, MAPGEN_FRAME_TICKS};
+    use crate::map::Map;
+    use crate::server::gamestate::RunState;
+    use crate::server::levels::LevelTransition;
+
+    #[test]
+    fn test_loading_restores_the_clock_and_forgets_the_stairs() {
+        let settings = ServerSettings {
+            seed: Some(3),
+            show_map_generation: false,
+        };
+        let mut server = Server::load_from_path("static/data", settings).unwrap();
+        for _ in 0..5 {
+            server.tick();
+        }
+        let save = server.save();
+        for _ in 0..5 {
+            server.tick();
+        }
+        server.resources.get_mut::<LevelTransition>().unwrap().depth = Some(1);
+
+        server.load(save);
+        assert_eq!(server.ticks(), 5);
+        assert_eq!(
+            server.resources.get::<LevelTransition>().unwrap().depth,
+            None
+        );
+        server.tick();
+        assert_eq!(server.ticks(), 6);
+    }
+
+    #[test]
+    fn test_map_generation_plays_back_tick_by_tick() {
+        let settings = ServerSettings {
+            seed: Some(3),
+            show_map_generation: true,
+        };
+        let mut server = Server::load_from_path("static/data", settings).unwrap();
+        let frames = server.map_state.mapgen_built_map.history.len();
+        assert!(frames > 1);
+        assert_eq!(server.run_state, RunState::MapGeneration);
+
+        for _ in 0..MAPGEN_FRAME_TICKS - 1 {
+            server.tick();
+        }
+        let first = server.map_state.mapgen_built_map.history[0].clone();
+        assert_eq!(*server.resources.get::<Map>().unwrap(), first);
+        server.tick();
+        let second = server.map_state.mapgen_built_map.history[1].clone();
+        assert_eq!(*server.resources.get::<Map>().unwrap(), second);
+
+        for _ in MAPGEN_FRAME_TICKS..MAPGEN_FRAME_TICKS * frames as u32 {
+            server.tick();
+        }
+        assert_eq!(server.run_state, RunState::Initializing);
+        assert_eq!(server.ticks(), 0);
+    }
+
+    #[test]
+    fn test_nothing_happens_while_paused() {
+        let settings = ServerSettings {
+            seed: Some(3),
+            show_map_generation: false,
+        };
+        let mut server = Server::load_from_path("static/data", settings).unwrap();
+        server.tick();
+        let hash = server.state_hash();
+        server.run_state = RunState::Paused;
+        for _ in 0..10 {
+            server.tick();
+        }
+        assert_eq!(server.ticks(), 1);
+        assert_eq!(server.state_hash(), hash);
+    }
+}
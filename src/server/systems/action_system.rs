@@ -0,0 +1,804 @@
+use crate::color::Color;
+use crate::component::{
+    ActiveTurn, DisplayCabinet, Inventory, Name, Player, Position, Renderable, TurnState, Value,
+    Viewshed, Wallet,
+};
+use crate::geom::{Point, Vector};
+use crate::map::{Map, TileType};
+use crate::message::Message;
+use crate::server::action::{Action, ActionResult, ActionResults, Intent};
+use crate::server::levels::LevelTransition;
+use crate::server::server::MessageQueue;
+use crate::tiles::TileDefinitions;
+use legion::prelude::*;
+
+/// Everything outside the acting entities that an action might need to look at or change.
+struct ActionContext<'a> {
+    map: &'a mut Map,
+    tiles: &'a TileDefinitions,
+    message_queue: &'a mut MessageQueue,
+    transition: &'a mut LevelTransition,
+    /// Whoever runs the shop, and so owns every display cabinet.
+    owner: Option<Entity>,
+    /// Set when a tile starts or stops blocking sight, so everyone has to look again.
+    sight_changed: &'a mut bool,
+}
+
+/// Carries out the intent of whichever entity currently has the turn. Successful actions
+/// end the turn at the action's cost; failed ones leave the player's turn open for
+/// another try. Anyone else loses the turn as if they'd waited, so a confused NPC can't
+/// hold up the game.
+pub fn action_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("action_system")
+        .write_resource::<Map>()
+        .read_resource::<TileDefinitions>()
+        .write_resource::<MessageQueue>()
+        .write_resource::<ActionResults>()
+        .write_resource::<LevelTransition>()
+        .with_query(<(Read<Intent>, Read<ActiveTurn>)>::query())
+        .with_query(<Read<Position>>::query().filter(tag::<Player>()))
+        .with_query(<Write<Viewshed>>::query())
+        .write_component::<ActiveTurn>()
+        .write_component::<Position>()
+        .write_component::<Inventory>()
+        .write_component::<Renderable>()
+        .read_component::<Name>()
+        .read_component::<Value>()
+        .write_component::<Wallet>()
+        .write_component::<Viewshed>()
+        .build(
+            move |command_buffer,
+                  world,
+                  (map, tiles, message_queue, results, transition),
+                  (intent_query, owner_query, viewshed_query)| {
+                let map: &mut Map = map;
+                let tiles: &TileDefinitions = tiles;
+                let message_queue: &mut MessageQueue = message_queue;
+                let transition: &mut LevelTransition = transition;
+                // The player runs the shop, so every cabinet sale goes through their wallet.
+                let owner = owner_query
+                    .iter_entities(world)
+                    .next()
+                    .map(|(entity, _)| entity);
+                let ready = intent_query
+                    .iter_entities(world)
+                    .filter(|(_, (_, turn))| turn.state == TurnState::PENDING)
+                    .map(|(entity, (intent, _))| (entity, intent.action))
+                    .collect::<Vec<_>>();
+
+                let mut sight_changed = false;
+                for (actor, action) in ready {
+                    let context = ActionContext {
+                        map: &mut *map,
+                        tiles,
+                        message_queue: &mut *message_queue,
+                        transition: &mut *transition,
+                        owner,
+                        sight_changed: &mut sight_changed,
+                    };
+                    let success = perform(world, context, actor, action);
+                    let cost = if success {
+                        Some(action.cost())
+                    } else if world.get_tag::<Player>(actor).is_none() {
+                        Some(Action::Wait.cost())
+                    } else {
+                        None
+                    };
+                    if let Some(cost) = cost {
+                        if let Some(mut turn) = world.get_component_mut::<ActiveTurn>(actor) {
+                            turn.finish(cost);
+                        }
+                    }
+                    command_buffer.remove_component::<Intent>(actor);
+                    results.results.push(ActionResult {
+                        actor,
+                        action,
+                        success,
+                    });
+                }
+                if sight_changed {
+                    for mut viewshed in viewshed_query.iter_mut(world) {
+                        viewshed.dirty = true;
+                    }
+                }
+            },
+        )
+}
+
+fn perform(world: &mut SubWorld, context: ActionContext, actor: Entity, action: Action) -> bool {
+    let ActionContext {
+        map,
+        tiles,
+        message_queue,
+        transition,
+        owner,
+        sight_changed,
+    } = context;
+    // Nobody reaches further than the next tile over, whatever a client asks for.
+    if let Some((dx, dy)) = action.direction() {
+        if dx.abs() > 1 || dy.abs() > 1 {
+            return false;
+        }
+    }
+    let position: Point = match world.get_component::<Position>(actor) {
+        Some(position) => (*position).into(),
+        None => return false,
+    };
+    let is_player = world.get_tag::<Player>(actor).is_some();
+    // Anything in the way of `actor` in the given direction, other than itself.
+    let target = |dx: i32, dy: i32| {
+        map.content_at(position + Vector::new(dx, dy))
+            .filter(|target| *target != actor)
+    };
+
+    match action {
+        Action::Wait => true,
+        // Only the player moves between levels; everyone else stays where they belong.
+        Action::Descend | Action::Ascend => {
+            let (stairs, depth) = match action {
+                Action::Descend => (TileType::DownStairs, map.depth + 1),
+                _ => (TileType::UpStairs, map.depth - 1),
+            };
+            if !is_player || map.get_type(position) != stairs {
+                return false;
+            }
+            transition.depth = Some(depth);
+            true
+        }
+        Action::Move { dx, dy } => {
+            let desired = position + Vector::new(dx, dy);
+            if !map.in_bounds(desired) || map.is_blocked(desired) {
+                if is_player {
+                    message_queue.push(Message::GameEvent(
+                        "Ouch, you hit a wall!".to_string(),
+                        Some(Color::RED),
+                        None,
+                    ));
+                }
+                false
+            } else if target(dx, dy).is_some() {
+                false
+            } else {
+                let mut position = world.get_component_mut::<Position>(actor).unwrap();
+                position.x = desired.x;
+                position.y = desired.y;
+                if let Some(mut viewshed) = world.get_component_mut::<Viewshed>(actor) {
+                    viewshed.dirty = true;
+                }
+                true
+            }
+        }
+        Action::Interact { dx, dy } => {
+            let target = match target(dx, dy) {
+                Some(target) => target,
+                // With nothing standing there, try the tile itself, like opening a door.
+                None => {
+                    let point = position + Vector::new(dx, dy);
+                    if !map.in_bounds(point) {
+                        return false;
+                    }
+                    let toggle = match tiles.get(map.get_type(point)).toggle {
+                        Some(toggle) => toggle,
+                        None => return false,
+                    };
+                    let index = map.point_to_index(point);
+                    let was_opaque = map.opaque[index];
+                    map.set_type(point, toggle);
+                    map.refresh_blocked(tiles);
+                    if map.opaque[index] != was_opaque {
+                        *sight_changed = true;
+                    }
+                    if is_player {
+                        message_queue.push(Message::GameEvent(
+                            tiles.get(toggle).description.clone(),
+                            None,
+                            None,
+                        ));
+                    }
+                    return true;
+                }
+            };
+            match world.get_component_mut::<Renderable>(target) {
+                Some(mut renderable) => {
+                    if renderable.glyph.foreground != Some(Color::RED) {
+                        renderable.glyph.foreground = Some(Color::RED);
+                    } else {
+                        renderable.glyph.foreground = Some(Color::GREEN);
+                    }
+                    true
+                }
+                None => false,
+            }
+        }
+        Action::Inspect { dx, dy } => {
+            let target = match target(dx, dy) {
+                Some(target) => target,
+                None => return false,
+            };
+            let contents = match world.get_component::<Inventory>(target) {
+                Some(inventory) => inventory.contents.clone(),
+                None => return false,
+            };
+            let text = match contents.last() {
+                Some(item) => format!(
+                    "{} looks at the {}.",
+                    name_of(world, actor),
+                    name_of(world, *item)
+                ),
+                None => format!(
+                    "{} peers into the empty {}.",
+                    name_of(world, actor),
+                    name_of(world, target)
+                ),
+            };
+            message_queue.push(Message::GameEvent(text, None, None));
+            true
+        }
+        Action::Take { dx, dy } => {
+            let target = match target(dx, dy) {
+                Some(target) => target,
+                None => return false,
+            };
+            if !has_room(world, actor) {
+                return false;
+            }
+            let item = match world
+                .get_component::<Inventory>(target)
+                .and_then(|inventory| inventory.contents.last().cloned())
+            {
+                Some(item) => item,
+                None => return false,
+            };
+            // Anything worth money has to be bought, unless it's already the taker's own
+            // stock on show in their shop.
+            let free = match world.get_tag::<DisplayCabinet>(target) {
+                Some(_) => owner == Some(actor),
+                None => world.get_component::<Value>(item).is_none(),
+            };
+            if !free {
+                return false;
+            }
+            world
+                .get_component_mut::<Inventory>(target)
+                .unwrap()
+                .contents
+                .pop();
+            world
+                .get_component_mut::<Inventory>(actor)
+                .unwrap()
+                .contents
+                .push(item);
+            if is_player {
+                if let Some(name) = world.get_component::<Name>(item) {
+                    message_queue.push(Message::GameEvent(
+                        format!("You took {:?}", name.name),
+                        Some(Color::GREEN),
+                        None,
+                    ));
+                }
+            }
+            true
+        }
+        Action::Put { dx, dy, slot } => {
+            let target = match target(dx, dy) {
+                Some(target) => target,
+                None => return false,
+            };
+            if !has_room(world, target) {
+                return false;
+            }
+            let item = match world.get_component_mut::<Inventory>(actor) {
+                Some(mut inventory) if slot < inventory.contents.len() => {
+                    inventory.contents.remove(slot)
+                }
+                _ => return false,
+            };
+            world
+                .get_component_mut::<Inventory>(target)
+                .unwrap()
+                .contents
+                .push(item);
+            true
+        }
+        Action::Buy { dx, dy } => {
+            let target = match target(dx, dy) {
+                Some(target) => target,
+                None => return false,
+            };
+            let seller = counterparty(world, target, owner);
+            if seller == Some(actor) {
+                return false;
+            }
+            let item = match world
+                .get_component::<Inventory>(target)
+                .and_then(|inventory| inventory.contents.last().cloned())
+            {
+                Some(item) => item,
+                None => return false,
+            };
+            let price = match world.get_component::<Value>(item) {
+                Some(value) => value.price,
+                None => return false,
+            };
+            let can_afford = world
+                .get_component::<Wallet>(actor)
+                .map_or(false, |wallet| wallet.coins >= price);
+            if !can_afford || !has_room(world, actor) || !has_wallet(world, seller) {
+                return false;
+            }
+
+            world
+                .get_component_mut::<Inventory>(target)
+                .unwrap()
+                .contents
+                .pop();
+            world
+                .get_component_mut::<Inventory>(actor)
+                .unwrap()
+                .contents
+                .push(item);
+            world.get_component_mut::<Wallet>(actor).unwrap().coins -= price;
+            if let Some(seller) = seller {
+                world.get_component_mut::<Wallet>(seller).unwrap().coins += price;
+            }
+            let buyer = if is_player {
+                "You".to_string()
+            } else {
+                name_of(world, actor)
+            };
+            message_queue.push(Message::GameEvent(
+                format!(
+                    "{} bought the {} for {} coins.",
+                    buyer,
+                    name_of(world, item),
+                    price
+                ),
+                Some(Color::YELLOW),
+                None,
+            ));
+            true
+        }
+        Action::Sell { dx, dy, slot } => {
+            let target = match target(dx, dy) {
+                Some(target) => target,
+                None => return false,
+            };
+            let buyer = counterparty(world, target, owner);
+            if buyer == Some(actor) || !has_room(world, target) {
+                return false;
+            }
+            let item = match world
+                .get_component::<Inventory>(actor)
+                .and_then(|inventory| inventory.contents.get(slot).cloned())
+            {
+                Some(item) => item,
+                None => return false,
+            };
+            let payout = match world.get_component::<Value>(item) {
+                Some(value) => trade_in_price(value.price),
+                None => return false,
+            };
+            // The wholesaler always has the money; the shop only has what's in the till.
+            let can_afford = buyer.map_or(true, |buyer| {
+                world
+                    .get_component::<Wallet>(buyer)
+                    .map_or(false, |wallet| wallet.coins >= payout)
+            });
+            if !can_afford || !has_wallet(world, Some(actor)) {
+                return false;
+            }
+
+            world
+                .get_component_mut::<Inventory>(actor)
+                .unwrap()
+                .contents
+                .remove(slot);
+            world
+                .get_component_mut::<Inventory>(target)
+                .unwrap()
+                .contents
+                .push(item);
+            if let Some(buyer) = buyer {
+                world.get_component_mut::<Wallet>(buyer).unwrap().coins -= payout;
+            }
+            world.get_component_mut::<Wallet>(actor).unwrap().coins += payout;
+            let text = if is_player {
+                format!(
+                    "You sold the {} for {} coins.",
+                    name_of(world, item),
+                    payout
+                )
+            } else {
+                format!(
+                    "{} sold you a {} for {} coins.",
+                    name_of(world, actor),
+                    name_of(world, item),
+                    payout
+                )
+            };
+            message_queue.push(Message::GameEvent(text, Some(Color::YELLOW), None));
+            true
+        }
+    }
+}
+
+/// Who a trade with `target` is with. Display cabinets belong to the shop; anything
+/// else, like a crate of stock, is the wholesaler's, who isn't anyone in the world.
+fn counterparty(world: &SubWorld, target: Entity, owner: Option<Entity>) -> Option<Entity> {
+    if world.get_tag::<DisplayCabinet>(target).is_some() {
+        owner
+    } else {
+        None
+    }
+}
+
+/// Whether `entity` can hold one more item.
+fn has_room(world: &SubWorld, entity: Entity) -> bool {
+    world
+        .get_component::<Inventory>(entity)
+        .map_or(false, |inventory| {
+            inventory.contents.len() < inventory.capacity as usize
+        })
+}
+
+/// Whether there's somewhere for the money to go, remembering the wholesaler has no
+/// wallet to worry about.
+fn has_wallet(world: &SubWorld, entity: Option<Entity>) -> bool {
+    entity.map_or(true, |entity| {
+        world.get_component::<Wallet>(entity).is_some()
+    })
+}
+
+/// What the shop pays for an item it buys in, leaving room for a profit.
+pub fn trade_in_price(price: u32) -> u32 {
+    price / 2
+}
+
+fn name_of(world: &SubWorld, entity: Entity) -> String {
+    world
+        .get_component::<Name>(entity)
+        .map(|name| name.name.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{action_system, trade_in_price};
+    use crate::component::{
+        ActiveTurn, DisplayCabinet, Inventory, Player, Position, Value, Viewshed, Wallet,
+    };
+    use crate::geom::Point;
+    use crate::map::{Map, TileType};
+    use crate::server::action::{Action, ActionResults, Intent};
+    use crate::server::levels::LevelTransition;
+    use crate::server::server::MessageQueue;
+    use crate::tiles::TileDefinitions;
+    use legion::prelude::*;
+
+    const BUY: Action = Action::Buy { dx: 1, dy: 0 };
+    const SELL: Action = Action::Sell {
+        dx: 1,
+        dy: 0,
+        slot: 0,
+    };
+    const TAKE: Action = Action::Take { dx: 1, dy: 0 };
+
+    fn item(world: &mut World, price: u32) -> Entity {
+        world.insert((), vec![(Value { price },)])[0]
+    }
+
+    /// Something to trade with, just east of whoever is acting.
+    fn stock(world: &mut World, contents: Vec<Entity>, capacity: u8, cabinet: bool) -> Entity {
+        let position = Position { x: 1, y: 0 };
+        let inventory = Inventory { contents, capacity };
+        if cabinet {
+            world.insert((DisplayCabinet,), vec![(position, inventory)])[0]
+        } else {
+            world.insert((), vec![(position, inventory)])[0]
+        }
+    }
+
+    /// A customer at the west end of the shop, about to carry out `action`.
+    fn customer(
+        world: &mut World,
+        coins: u32,
+        contents: Vec<Entity>,
+        capacity: u8,
+        action: Action,
+    ) -> Entity {
+        world.insert(
+            (),
+            vec![(
+                Position { x: 0, y: 0 },
+                Wallet { coins },
+                Inventory { contents, capacity },
+                Intent { action },
+                ActiveTurn::pending(),
+            )],
+        )[0]
+    }
+
+    /// The shopkeeper, minding the till at the east end of the shop.
+    fn shopkeeper(world: &mut World, coins: u32) -> Entity {
+        world.insert(
+            (Player,),
+            vec![(
+                Position { x: 2, y: 0 },
+                Wallet { coins },
+                Inventory {
+                    contents: vec![],
+                    capacity: 2,
+                },
+            )],
+        )[0]
+    }
+
+    /// The shopkeeper at the west end of the shop, about to carry out `action`.
+    fn player(
+        world: &mut World,
+        coins: u32,
+        contents: Vec<Entity>,
+        capacity: u8,
+        action: Action,
+    ) -> Entity {
+        world.insert(
+            (Player,),
+            vec![(
+                Position { x: 0, y: 0 },
+                Wallet { coins },
+                Inventory { contents, capacity },
+                Intent { action },
+                ActiveTurn::pending(),
+            )],
+        )[0]
+    }
+
+    fn tiles() -> TileDefinitions {
+        TileDefinitions::load_from_path("static/data/tiles.json").unwrap()
+    }
+
+    /// A three tile shop, with `middle` between the actor and the shopkeeper.
+    fn shop_floor(middle: TileType) -> Map {
+        let mut map = Map::new((3, 1), 0);
+        for x in 0..3 {
+            map.set_type(Point::new(x, 0), TileType::Floor);
+        }
+        map.set_type(Point::new(1, 0), middle);
+        map.refresh_blocked(&tiles());
+        map.refresh_content();
+        map
+    }
+
+    fn execute(world: &mut World, map: Map) -> Resources {
+        let mut resources = Resources::default();
+        resources.insert(map);
+        resources.insert(tiles());
+        resources.insert(MessageQueue::default());
+        resources.insert(ActionResults::default());
+        resources.insert(LevelTransition::default());
+        let mut schedule = Schedule::builder().add_system(action_system()).build();
+        schedule.execute(world, &mut resources);
+        resources
+    }
+
+    /// Runs the action system with `target` in the middle of the shop and says whether
+    /// the action went through.
+    fn run(world: &mut World, target: Entity) -> bool {
+        let mut map = shop_floor(TileType::Floor);
+        let index = map.coord_to_index(1, 0);
+        map.tile_content[index] = Some(target);
+
+        let resources = execute(world, map);
+        let results = resources.get::<ActionResults>().unwrap();
+        assert_eq!(results.results.len(), 1);
+        results.results[0].success
+    }
+
+    fn coins(world: &World, entity: Entity) -> u32 {
+        world.get_component::<Wallet>(entity).unwrap().coins
+    }
+
+    fn contents(world: &World, entity: Entity) -> Vec<Entity> {
+        world
+            .get_component::<Inventory>(entity)
+            .unwrap()
+            .contents
+            .clone()
+    }
+
+    #[test]
+    fn test_customers_pay_the_shop_for_what_is_on_show() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let star = item(&mut world, 5);
+        let cabinet = stock(&mut world, vec![star], 1, true);
+        let shop = shopkeeper(&mut world, 0);
+        let buyer = customer(&mut world, 40, vec![], 2, BUY);
+
+        assert!(run(&mut world, cabinet));
+        assert_eq!(coins(&world, buyer), 35);
+        assert_eq!(coins(&world, shop), 5);
+        assert_eq!(contents(&world, buyer), vec![star]);
+        assert!(contents(&world, cabinet).is_empty());
+    }
+
+    #[test]
+    fn test_buying_needs_the_money() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let star = item(&mut world, 5);
+        let cabinet = stock(&mut world, vec![star], 1, true);
+        let shop = shopkeeper(&mut world, 0);
+        let buyer = customer(&mut world, 3, vec![], 2, BUY);
+
+        assert!(!run(&mut world, cabinet));
+        assert_eq!(coins(&world, buyer), 3);
+        assert_eq!(coins(&world, shop), 0);
+        assert_eq!(contents(&world, cabinet), vec![star]);
+    }
+
+    #[test]
+    fn test_buying_needs_room_to_carry_it() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let (star, club) = (item(&mut world, 5), item(&mut world, 1));
+        let cabinet = stock(&mut world, vec![star], 1, true);
+        let shop = shopkeeper(&mut world, 0);
+        let buyer = customer(&mut world, 40, vec![club], 1, BUY);
+
+        assert!(!run(&mut world, cabinet));
+        assert_eq!(coins(&world, buyer), 40);
+        assert_eq!(coins(&world, shop), 0);
+        assert_eq!(contents(&world, buyer), vec![club]);
+        assert_eq!(contents(&world, cabinet), vec![star]);
+    }
+
+    #[test]
+    fn test_the_shop_pays_for_what_customers_sell() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let star = item(&mut world, 8);
+        let cabinet = stock(&mut world, vec![], 1, true);
+        let shop = shopkeeper(&mut world, 10);
+        let seller = customer(&mut world, 0, vec![star], 2, SELL);
+
+        assert!(run(&mut world, cabinet));
+        assert_eq!(coins(&world, seller), trade_in_price(8));
+        assert_eq!(coins(&world, shop), 10 - trade_in_price(8));
+        assert_eq!(contents(&world, cabinet), vec![star]);
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let star = item(&mut world, 8);
+        let cabinet = stock(&mut world, vec![], 1, true);
+        let shop = shopkeeper(&mut world, 3);
+        let seller = customer(&mut world, 0, vec![star], 2, SELL);
+
+        assert!(!run(&mut world, cabinet));
+        assert_eq!(coins(&world, seller), 0);
+        assert_eq!(coins(&world, shop), 3);
+        assert_eq!(contents(&world, seller), vec![star]);
+    }
+
+    #[test]
+    fn test_the_player_buys_stock_from_crates() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let diamond = item(&mut world, 10);
+        let crate_ = stock(&mut world, vec![diamond], 2, false);
+        let shop = player(&mut world, 12, vec![], 2, BUY);
+
+        assert!(run(&mut world, crate_));
+        assert_eq!(coins(&world, shop), 2);
+        assert_eq!(contents(&world, shop), vec![diamond]);
+
+        // There's no buying from yourself.
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let diamond = item(&mut world, 10);
+        let cabinet = stock(&mut world, vec![diamond], 1, true);
+        let shop = player(&mut world, 12, vec![], 2, BUY);
+
+        assert!(!run(&mut world, cabinet));
+        assert_eq!(coins(&world, shop), 12);
+    }
+
+    #[test]
+    fn test_only_your_own_stock_is_free_to_take() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let diamond = item(&mut world, 10);
+        let crate_ = stock(&mut world, vec![diamond], 2, false);
+        let shop = player(&mut world, 0, vec![], 2, TAKE);
+        assert!(!run(&mut world, crate_));
+        assert!(contents(&world, shop).is_empty());
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let diamond = item(&mut world, 10);
+        let cabinet = stock(&mut world, vec![diamond], 1, true);
+        shopkeeper(&mut world, 0);
+        let thief = customer(&mut world, 0, vec![], 2, TAKE);
+        assert!(!run(&mut world, cabinet));
+        assert!(contents(&world, thief).is_empty());
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let (diamond, club) = (item(&mut world, 10), item(&mut world, 1));
+        let cabinet = stock(&mut world, vec![diamond], 1, true);
+        let shop = player(&mut world, 0, vec![club], 1, TAKE);
+        assert!(!run(&mut world, cabinet));
+        assert_eq!(contents(&world, shop), vec![club]);
+        assert_eq!(contents(&world, cabinet), vec![diamond]);
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let diamond = item(&mut world, 10);
+        let cabinet = stock(&mut world, vec![diamond], 1, true);
+        let shop = player(&mut world, 0, vec![], 2, TAKE);
+        assert!(run(&mut world, cabinet));
+        assert_eq!(contents(&world, shop), vec![diamond]);
+    }
+
+    #[test]
+    fn test_nothing_reaches_past_the_next_tile() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let shop = player(&mut world, 0, vec![], 1, Action::Move { dx: 2, dy: 0 });
+        let resources = execute(&mut world, shop_floor(TileType::Wall));
+        assert!(!resources.get::<ActionResults>().unwrap().results[0].success);
+        assert_eq!(
+            *world.get_component::<Position>(shop).unwrap(),
+            Position { x: 0, y: 0 }
+        );
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let diamond = item(&mut world, 10);
+        let crate_ = world.insert(
+            (),
+            vec![(
+                Position { x: 2, y: 0 },
+                Inventory {
+                    contents: vec![diamond],
+                    capacity: 1,
+                },
+            )],
+        )[0];
+        let buyer = customer(&mut world, 40, vec![], 2, Action::Buy { dx: 2, dy: 0 });
+        let mut map = shop_floor(TileType::Floor);
+        let index = map.coord_to_index(2, 0);
+        map.tile_content[index] = Some(crate_);
+        let resources = execute(&mut world, map);
+        assert!(!resources.get::<ActionResults>().unwrap().results[0].success);
+        assert_eq!(coins(&world, buyer), 40);
+        assert_eq!(contents(&world, crate_), vec![diamond]);
+    }
+
+    #[test]
+    fn test_opening_a_door_makes_everyone_look_again() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        player(&mut world, 0, vec![], 1, Action::Interact { dx: 1, dy: 0 });
+        let onlooker = world.insert(
+            (),
+            vec![(
+                Position { x: 2, y: 0 },
+                Viewshed {
+                    range: 4,
+                    visible_tiles: vec![],
+                    dirty: false,
+                },
+            )],
+        )[0];
+
+        let resources = execute(&mut world, shop_floor(TileType::DoorClosed));
+        assert!(resources.get::<ActionResults>().unwrap().results[0].success);
+        let map = resources.get::<Map>().unwrap();
+        let door = Point::new(1, 0);
+        assert_eq!(map.get_type(door), TileType::DoorOpen);
+        assert!(!map.is_blocked(door));
+        assert!(!map.blocks_sight(door));
+        assert!(world.get_component::<Viewshed>(onlooker).unwrap().dirty);
+    }
+}
@@ -0,0 +1,444 @@
+use crate::component::{
+    ActiveTurn, Customer, CustomerSpawner, CustomerState, DisplayCabinet, Inventory, Position,
+    TurnState, Value, Wallet,
+};
+use crate::geom::{Point, Vector};
+use crate::map::Map;
+use crate::server::action::{Action, Intent};
+use crate::server::rng::RandomNumberGenerator;
+use crate::server::serializers::entity_factory::EntityFactory;
+use crate::server::systems::turn_system::cost;
+use legion::prelude::*;
+use pathfinding::prelude::astar;
+use rand::Rng;
+
+fn distance(a: Point, b: Point) -> i32 {
+    (a.x - b.x).abs() + (a.y - b.y).abs()
+}
+
+/// First step along the shortest walkable path from `from` to any tile within `range`
+/// of `goal`. Occupied tiles count as walls, so customers walk around each other.
+fn step_towards(map: &Map, from: Point, goal: Point, range: i32) -> Option<Point> {
+    let (path, _) = astar(
+        &(from.x, from.y),
+        |&(x, y)| {
+            [(0, 1), (0, -1), (1, 0), (-1, 0)]
+                .iter()
+                .map(move |(dx, dy)| Point::new(x + dx, y + dy))
+                .filter(|point| {
+                    map.in_bounds(*point)
+                        && !map.is_blocked(*point)
+                        && map.content_at(*point).is_none()
+                })
+                .map(|point| ((point.x, point.y), 1))
+                .collect::<Vec<_>>()
+        },
+        |&(x, y)| (distance(Point::new(x, y), goal) - range).max(0),
+        |&(x, y)| distance(Point::new(x, y), goal) <= range,
+    )?;
+    path.get(1).map(|&(x, y)| Point::new(x, y))
+}
+
+/// Picks the next action for a customer, or `None` once it has left the level.
+fn decide(
+    customer: &mut Customer,
+    position: Point,
+    map: &Map,
+    cabinets: &[Point],
+    rng: &mut RandomNumberGenerator,
+) -> Option<Action> {
+    if let CustomerState::Browsing { target: None } = customer.state {
+        customer.state = if customer.visits_left == 0 || cabinets.is_empty() {
+            CustomerState::Leaving
+        } else {
+            CustomerState::Browsing {
+                target: Some(cabinets[rng.gen_range(0, cabinets.len())]),
+            }
+        };
+    }
+
+    match customer.state {
+        CustomerState::Browsing { target: None } => unreachable!(),
+        CustomerState::Browsing {
+            target: Some(cabinet),
+        } => {
+            if distance(position, cabinet) == 1 {
+                customer.visits_left = customer.visits_left.saturating_sub(1);
+                customer.state = CustomerState::Browsing { target: None };
+                return Some(Action::Inspect {
+                    dx: cabinet.x - position.x,
+                    dy: cabinet.y - position.y,
+                });
+            }
+            match step_towards(map, position, cabinet, 1) {
+                Some(step) => Some(Action::Move {
+                    dx: step.x - position.x,
+                    dy: step.y - position.y,
+                }),
+                // Can't get there right now, so find something else to look at.
+                None => {
+                    customer.visits_left = customer.visits_left.saturating_sub(1);
+                    customer.state = CustomerState::Browsing { target: None };
+                    Some(Action::Wait)
+                }
+            }
+        }
+        CustomerState::Leaving => {
+            let entrance = map.entrance()?;
+            if position == entrance {
+                return None;
+            }
+            match step_towards(map, position, entrance, 0) {
+                Some(step) => Some(Action::Move {
+                    dx: step.x - position.x,
+                    dy: step.y - position.y,
+                }),
+                None => Some(Action::Wait),
+            }
+        }
+    }
+}
+
+/// Turns a look at a display cabinet into a purchase or a sale when the customer is
+/// in the mood and the numbers work out. The action system has the final say.
+fn haggle(
+    world: &SubWorld,
+    customer: Entity,
+    cabinet: Option<Entity>,
+    action: Action,
+    rng: &mut RandomNumberGenerator,
+) -> Action {
+    let (dx, dy) = match action {
+        Action::Inspect { dx, dy } => (dx, dy),
+        action => return action,
+    };
+    let cabinet = match cabinet.and_then(|cabinet| world.get_component::<Inventory>(cabinet)) {
+        Some(cabinet) => cabinet,
+        None => return action,
+    };
+    if !rng.gen_bool(0.5) {
+        return action;
+    }
+    let coins = world
+        .get_component::<Wallet>(customer)
+        .map_or(0, |wallet| wallet.coins);
+    match cabinet.contents.last() {
+        Some(item) => {
+            let price = world.get_component::<Value>(*item).map(|value| value.price);
+            match price {
+                Some(price) if price <= coins => Action::Buy { dx, dy },
+                _ => action,
+            }
+        }
+        None => {
+            let has_stock = world
+                .get_component::<Inventory>(customer)
+                .map_or(false, |inventory| !inventory.contents.is_empty());
+            if has_stock {
+                Action::Sell { dx, dy, slot: 0 }
+            } else {
+                action
+            }
+        }
+    }
+}
+
+/// Gives customers something to do on their turn: walk to a display cabinet, look at
+/// what's in it and eventually head back out the entrance, where they disappear.
+pub fn customer_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("customer_system")
+        .read_resource::<Map>()
+        .write_resource::<RandomNumberGenerator>()
+        .with_query(<(Read<Position>, Read<ActiveTurn>)>::query().filter(component::<Customer>()))
+        .with_query(<Read<Position>>::query().filter(tag::<DisplayCabinet>()))
+        .write_component::<Customer>()
+        .read_component::<Intent>()
+        .read_component::<Inventory>()
+        .read_component::<Value>()
+        .read_component::<Wallet>()
+        .build(
+            move |command_buffer, world, (map, rng), (customer_query, cabinet_query)| {
+                let map: &Map = map;
+                let cabinets = cabinet_query
+                    .iter(world)
+                    .map(|position| (*position).into())
+                    .collect::<Vec<Point>>();
+                let ready = customer_query
+                    .iter_entities(world)
+                    .filter(|(_, (_, turn))| turn.state == TurnState::PENDING)
+                    .map(|(entity, (position, _))| (entity, (*position).into()))
+                    .collect::<Vec<(Entity, Point)>>();
+
+                for (entity, position) in ready {
+                    // Intents are only picked up once the command buffer is flushed.
+                    if world.get_component::<Intent>(entity).is_some() {
+                        continue;
+                    }
+                    let action = {
+                        let mut customer = world.get_component_mut::<Customer>(entity).unwrap();
+                        decide(&mut customer, position, map, &cabinets, rng)
+                    };
+                    match action {
+                        Some(action) => {
+                            let cabinet = match action {
+                                Action::Inspect { dx, dy } => {
+                                    map.content_at(position + Vector::new(dx, dy))
+                                }
+                                _ => None,
+                            };
+                            let action = haggle(world, entity, cabinet, action, rng);
+                            command_buffer.add_component(entity, Intent { action });
+                        }
+                        // Whatever they bought leaves with them.
+                        None => {
+                            if let Some(inventory) = world.get_component::<Inventory>(entity) {
+                                for item in inventory.contents.iter() {
+                                    command_buffer.delete(*item);
+                                }
+                            }
+                            command_buffer.delete(entity);
+                        }
+                    }
+                }
+            },
+        )
+}
+
+/// Lets a new customer in on the spawner's turn, as long as the shop isn't full and
+/// nobody is standing in the entrance.
+pub fn customer_spawn_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("customer_spawn_system")
+        .read_resource::<Map>()
+        .read_resource::<EntityFactory>()
+        .with_query(<(Read<CustomerSpawner>, Write<ActiveTurn>)>::query())
+        .with_query(<Read<Customer>>::query())
+        .build(
+            move |command_buffer, world, (map, factory), (spawner_query, customer_query)| {
+                let customers = customer_query.iter(world).count();
+                let entrance = map
+                    .entrance()
+                    .filter(|entrance| map.content_at(*entrance).is_none());
+                for (spawner, mut turn) in spawner_query.iter_mut(world) {
+                    if turn.state != TurnState::PENDING {
+                        continue;
+                    }
+                    if let Some(entrance) = entrance {
+                        if customers < spawner.max_customers as usize {
+                            factory.build(&spawner.customer, Some(entrance), command_buffer);
+                        }
+                    }
+                    turn.finish(cost::WAIT);
+                }
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{customer_spawn_system, customer_system, decide};
+    use crate::component::{
+        ActiveTurn, Customer, CustomerSpawner, CustomerState, DisplayCabinet, Inventory, Position,
+        TurnState, Value, Wallet,
+    };
+    use crate::geom::{Point, Vector};
+    use crate::map::{Map, TileType};
+    use crate::server::action::{Action, Intent};
+    use crate::server::rng::RandomNumberGenerator;
+    use crate::server::serializers::entity_factory::EntityFactory;
+    use crate::tiles::TileDefinitions;
+    use legion::prelude::*;
+
+    /// A corridor with the entrance at its west end.
+    fn corridor() -> Map {
+        let mut map = Map::new((8, 3), 0);
+        map.set_type(Point::new(0, 1), TileType::Entrance);
+        for x in 1..7 {
+            map.set_type(Point::new(x, 1), TileType::Floor);
+        }
+        map.refresh_blocked(&TileDefinitions::load_from_path("static/data/tiles.json").unwrap());
+        map
+    }
+
+    fn browsing(visits_left: u8) -> Customer {
+        Customer {
+            state: CustomerState::Browsing { target: None },
+            visits_left,
+        }
+    }
+
+    fn run(system: Box<dyn Schedulable>, world: &mut World, resources: &mut Resources) {
+        let mut schedule = Schedule::builder().add_system(system).build();
+        schedule.execute(world, resources);
+    }
+
+    #[test]
+    fn test_browses_a_cabinet_then_leaves() {
+        let map = corridor();
+        let cabinets = [Point::new(5, 1)];
+        let mut customer = browsing(1);
+        let mut rng = RandomNumberGenerator::seeded(0);
+        let mut position = Point::new(1, 1);
+        let mut actions = vec![];
+        while let Some(action) = decide(&mut customer, position, &map, &cabinets, &mut rng) {
+            if let Action::Move { dx, dy } = action {
+                position += Vector::new(dx, dy);
+            }
+            actions.push(action);
+            assert!(actions.len() < 20, "never left: {:?}", actions);
+        }
+
+        let right = Action::Move { dx: 1, dy: 0 };
+        let left = Action::Move { dx: -1, dy: 0 };
+        let inspect = Action::Inspect { dx: 1, dy: 0 };
+        assert_eq!(
+            actions,
+            vec![right, right, right, inspect, left, left, left, left]
+        );
+        assert_eq!(position, map.entrance().unwrap());
+        assert_eq!(customer.state, CustomerState::Leaving);
+    }
+
+    #[test]
+    fn test_gives_up_on_cabinets_it_cannot_reach() {
+        let mut map = corridor();
+        map.blocked[map.coord_to_index(3, 1)] = true;
+        let mut customer = browsing(2);
+        let mut rng = RandomNumberGenerator::seeded(0);
+        let action = decide(
+            &mut customer,
+            Point::new(1, 1),
+            &map,
+            &[Point::new(5, 1)],
+            &mut rng,
+        );
+        assert_eq!(action, Some(Action::Wait));
+        assert_eq!(customer.visits_left, 1);
+
+        let action = decide(&mut customer, Point::new(1, 1), &map, &[], &mut rng);
+        assert_eq!(action, Some(Action::Move { dx: -1, dy: 0 }));
+        assert_eq!(customer.state, CustomerState::Leaving);
+    }
+
+    /// Puts a customer with `coins` next to a cabinet holding an item priced at 5, lets
+    /// the customer system pick an action and returns it.
+    fn haggle_with(coins: u32, seed: u64) -> Action {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let item = world.insert((), vec![(Value { price: 5 },)])[0];
+        let cabinet = world.insert(
+            (DisplayCabinet,),
+            vec![(
+                Position { x: 2, y: 1 },
+                Inventory {
+                    contents: vec![item],
+                    capacity: 1,
+                },
+            )],
+        )[0];
+        let customer = world.insert(
+            (),
+            vec![(
+                Position { x: 1, y: 1 },
+                ActiveTurn::pending(),
+                Customer {
+                    state: CustomerState::Browsing {
+                        target: Some(Point::new(2, 1)),
+                    },
+                    visits_left: 1,
+                },
+                Wallet { coins },
+                Inventory {
+                    contents: vec![],
+                    capacity: 2,
+                },
+            )],
+        )[0];
+        let mut map = corridor();
+        map.refresh_content();
+        let index = map.coord_to_index(2, 1);
+        map.tile_content[index] = Some(cabinet);
+        let mut resources = Resources::default();
+        resources.insert(map);
+        resources.insert(RandomNumberGenerator::seeded(seed));
+
+        run(customer_system(), &mut world, &mut resources);
+        world.get_component::<Intent>(customer).unwrap().action
+    }
+
+    #[test]
+    fn test_buys_only_what_it_can_afford() {
+        let inspect = Action::Inspect { dx: 1, dy: 0 };
+        let buy = Action::Buy { dx: 1, dy: 0 };
+        let actions = (0..16).map(|seed| haggle_with(10, seed));
+        let (bought, looked) = actions.partition::<Vec<_>, _>(|action| *action == buy);
+        assert!(!bought.is_empty());
+        assert!(!looked.is_empty());
+        assert!(looked.iter().all(|action| *action == inspect));
+
+        assert!((0..16).all(|seed| haggle_with(3, seed) == inspect));
+    }
+
+    #[test]
+    fn test_leaving_customers_take_their_shopping_with_them() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let item = world.insert((), vec![(Value { price: 5 },)])[0];
+        let customer = world.insert(
+            (),
+            vec![(
+                Position { x: 0, y: 1 },
+                ActiveTurn::pending(),
+                Customer {
+                    state: CustomerState::Leaving,
+                    visits_left: 0,
+                },
+                Inventory {
+                    contents: vec![item],
+                    capacity: 2,
+                },
+            )],
+        )[0];
+        let mut resources = Resources::default();
+        resources.insert(corridor());
+        resources.insert(RandomNumberGenerator::seeded(0));
+
+        run(customer_system(), &mut world, &mut resources);
+        assert!(!world.is_alive(customer));
+        assert!(!world.is_alive(item));
+    }
+
+    #[test]
+    fn test_spawns_customers_up_to_the_limit() {
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let spawner = world.insert(
+            (),
+            vec![(
+                CustomerSpawner {
+                    customer: "customer".to_string(),
+                    max_customers: 1,
+                },
+                ActiveTurn::pending(),
+            )],
+        )[0];
+        let mut resources = Resources::default();
+        resources.insert(corridor());
+        resources.insert(EntityFactory::load_from_path("static/data/entities.json").unwrap());
+
+        let customers = |world: &World| {
+            <(Read<Customer>, Read<Position>)>::query()
+                .iter(world)
+                .map(|(_, position)| *position)
+                .collect::<Vec<_>>()
+        };
+        for _ in 0..2 {
+            *world.get_component_mut::<ActiveTurn>(spawner).unwrap() = ActiveTurn::pending();
+            run(customer_spawn_system(), &mut world, &mut resources);
+            assert_eq!(
+                world.get_component::<ActiveTurn>(spawner).unwrap().state,
+                TurnState::DONE
+            );
+        }
+        assert_eq!(customers(&world), vec![Position { x: 0, y: 1 }]);
+    }
+}
@@ -1,14 +1,16 @@
 use crate::component::*;
 use crate::map::Map;
+use crate::tiles::TileDefinitions;
 use legion::prelude::*;
 
 pub fn index_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("map_indexer")
         .write_resource::<Map>()
+        .read_resource::<TileDefinitions>()
         .with_query(<(Read<Position>, Read<TileBlocker>)>::query())
-        .build(move |_, mut world, map, query_entity| {
+        .build(move |_, mut world, (map, tiles), query_entity| {
             let map: &mut Map = map;
-            map.refresh_blocked();
+            map.refresh_blocked(tiles);
             map.refresh_content();
             for (entity, (position, _)) in query_entity.iter_entities(&mut world) {
                 let index = map.coord_to_index(position.x, position.y);
@@ -0,0 +1,144 @@
+use crate::component::{LightSource, Position};
+use crate::geom::Point;
+use crate::map::Map;
+use crate::server::fov::FieldOfView;
+use legion::prelude::*;
+
+/// Light a source gives off at `distance` tiles away, fading to nothing just past the
+/// edge of its radius.
+fn falloff(light: &LightSource, distance: f32) -> f32 {
+    light.intensity * (1.0 - distance / (light.radius as f32 + 1.0)).max(0.0)
+}
+
+/// Works out the light on every tile of `map` from scratch. Light only reaches the
+/// tiles its source could see.
+fn illuminate(map: &mut Map, sources: &[(Point, LightSource)], fov: &mut FieldOfView) {
+    map.clear_light();
+    for (origin, light) in sources {
+        for point in fov.visible(*origin, light.radius, map) {
+            let (dx, dy) = ((point.x - origin.x) as f32, (point.y - origin.y) as f32);
+            let amount = falloff(light, (dx * dx + dy * dy).sqrt());
+            map.add_light(*point, light.color, amount);
+        }
+    }
+}
+
+/// Everything the light levels on the map were last worked out from.
+struct Lighting {
+    depth: i32,
+    sources: Vec<(Point, LightSource)>,
+    /// Which tiles blocked the light, so opening a door lets it through.
+    opaque: Vec<bool>,
+}
+
+/// Recomputes the map's light levels whenever a light source moves, appears, goes out,
+/// a tile starts or stops blocking sight, or the level changes.
+pub fn lighting_system() -> Box<dyn Schedulable> {
+    let mut last_lit: Option<Lighting> = None;
+    let mut fov = FieldOfView::default();
+    SystemBuilder::new("lighting_system")
+        .write_resource::<Map>()
+        .with_query(<(Read<Position>, Read<LightSource>)>::query())
+        .build(move |_, world, map, query| {
+            let map: &mut Map = map;
+            let mut sources = query
+                .iter(world)
+                .map(|(position, light)| ((*position).into(), *light))
+                .collect::<Vec<(Point, LightSource)>>();
+            // Always add the lights up in the same order, so rounding comes out the same.
+            sources.sort_by_key(|(point, _)| (point.y, point.x));
+            let unchanged = last_lit.as_ref().map_or(false, |last| {
+                last.depth == map.depth && last.sources == sources && last.opaque == map.opaque
+            });
+            if unchanged {
+                return;
+            }
+            illuminate(map, &sources, &mut fov);
+            last_lit = Some(Lighting {
+                depth: map.depth,
+                sources,
+                opaque: map.opaque.clone(),
+            });
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{illuminate, lighting_system};
+    use crate::color::Color;
+    use crate::component::{LightSource, Position};
+    use crate::geom::Point;
+    use crate::map::{Map, TileType};
+    use crate::server::fov::FieldOfView;
+    use crate::tiles::TileDefinitions;
+    use legion::prelude::*;
+
+    #[test]
+    fn test_light_fades_and_stops_at_walls() {
+        let mut map = Map::new((9, 3), 0);
+        for x in 1..8 {
+            map.set_type(Point::new(x, 1), TileType::Floor);
+        }
+        map.set_type(Point::new(5, 1), TileType::Wall);
+        map.refresh_blocked(&TileDefinitions::load_from_path("static/data/tiles.json").unwrap());
+        let lamp = LightSource {
+            radius: 6,
+            color: Color::WHITE,
+            intensity: 1.0,
+        };
+        illuminate(
+            &mut map,
+            &[(Point::new(1, 1), lamp)],
+            &mut FieldOfView::default(),
+        );
+
+        let level = |x| map.light[map.point_to_index(Point::new(x, 1))][0];
+        assert!(level(1) > level(2));
+        assert!(level(2) > level(4));
+        assert!(level(5) > 0.0);
+        assert_eq!(level(6), 0.0);
+    }
+
+    #[test]
+    fn test_relights_when_a_wall_opens_up() {
+        let tiles = TileDefinitions::load_from_path("static/data/tiles.json").unwrap();
+        let mut map = Map::new((5, 3), 0);
+        for x in 1..4 {
+            map.set_type(Point::new(x, 1), TileType::Floor);
+        }
+        map.set_type(Point::new(2, 1), TileType::Wall);
+        map.refresh_blocked(&tiles);
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        world.insert(
+            (),
+            vec![(
+                Position { x: 1, y: 1 },
+                LightSource {
+                    radius: 4,
+                    color: Color::WHITE,
+                    intensity: 1.0,
+                },
+            )],
+        );
+        let mut resources = Resources::default();
+        resources.insert(map);
+        let mut schedule = Schedule::builder().add_system(lighting_system()).build();
+        let beyond_the_wall = |resources: &Resources| {
+            let map = resources.get::<Map>().unwrap();
+            map.light[map.point_to_index(Point::new(3, 1))][0]
+        };
+
+        schedule.execute(&mut world, &mut resources);
+        assert_eq!(beyond_the_wall(&resources), 0.0);
+
+        {
+            let mut map = resources.get_mut::<Map>().unwrap();
+            map.set_type(Point::new(2, 1), TileType::Floor);
+            map.refresh_blocked(&tiles);
+        }
+        schedule.execute(&mut world, &mut resources);
+        assert!(beyond_the_wall(&resources) > 0.0);
+    }
+}
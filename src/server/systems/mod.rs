@@ -1,3 +1,7 @@
+pub mod action_system;
+pub mod customer_system;
 pub mod index_system;
+pub mod lighting_system;
 pub mod turn_system;
+pub mod viewshed_system;
 
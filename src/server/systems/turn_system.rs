@@ -1,58 +1,140 @@
-use crate::component::{ActiveTurn, Priority, TurnState};
+use crate::component::{ActiveTurn, Energy, Priority, Speed, TurnState};
 use legion::prelude::*;
+use std::collections::HashMap;
 
-pub struct PendingMoves {
-    list: Vec<Entity>,
+/// Energy an entity needs before it can take a turn.
+pub const TURN_ENERGY: i32 = 100;
+
+/// What each kind of action takes out of the acting entity's energy.
+pub mod cost {
+    pub const MOVE: i32 = 100;
+    pub const WAIT: i32 = 100;
+    pub const INTERACT: i32 = 50;
+    pub const INSPECT: i32 = 100;
+    pub const TAKE: i32 = 50;
+    pub const PUT: i32 = 50;
+    pub const BUY: i32 = 100;
+    pub const SELL: i32 = 100;
+    pub const STAIRS: i32 = 100;
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Actor<T> {
+    id: T,
+    energy: i32,
+    speed: i32,
+    priority: u8,
 }
 
-impl PendingMoves {
-    pub fn new() -> Self {
-        Self { list: vec![] }
+/// Moves time forward until at least one actor has a turn's worth of energy and picks
+/// the one with the most, breaking ties by priority. Returns `None` when nobody can
+/// ever act.
+fn next_actor<T: Copy>(actors: &mut [Actor<T>]) -> Option<T> {
+    let steps = actors
+        .iter()
+        .filter_map(|actor| {
+            if actor.energy >= TURN_ENERGY {
+                Some(0)
+            } else if actor.speed > 0 {
+                Some((TURN_ENERGY - actor.energy + actor.speed - 1) / actor.speed)
+            } else {
+                None
+            }
+        })
+        .min()?;
+    for actor in actors.iter_mut() {
+        actor.energy += actor.speed * steps;
     }
+    actors
+        .iter()
+        .filter(|actor| actor.energy >= TURN_ENERGY)
+        .max_by_key(|actor| (actor.energy, actor.priority))
+        .map(|actor| actor.id)
 }
 
 pub fn turn_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("turn_system")
-        .write_resource::<PendingMoves>()
         .with_query(<Read<ActiveTurn>>::query())
+        .with_query(<(Write<Energy>, Read<Speed>)>::query())
         .with_query(<Read<Priority>>::query())
         .build(
-            move |command_buffer, world, pending_moves, (turn_query, priority_query)| {
-                let active_entity = turn_query.iter_entities(world).next();
-                let still_active = match active_entity {
-                    Some((entity, active_turn)) => {
-                        if active_turn.state == TurnState::DONE {
-                            command_buffer.remove_component::<ActiveTurn>(entity);
-                            false
-                        } else {
-                            true
-                        }
+            move |command_buffer, world, _, (turn_query, energy_query, priority_query)| {
+                let finished = match turn_query.iter_entities(world).next() {
+                    Some((entity, turn)) if turn.state == TurnState::DONE => {
+                        Some((entity, turn.cost))
                     }
-                    None => false,
+                    Some(_) => return,
+                    None => None,
                 };
-
-                if !still_active {
-                    if pending_moves.list.is_empty() {
-                        let mut priority_tuple = vec![];
-
-                        for (entity, priority) in priority_query.iter_entities_mut(world) {
-                            priority_tuple.push((priority.value, entity));
+                if let Some((finished, cost)) = finished {
+                    command_buffer.remove_component::<ActiveTurn>(finished);
+                    for (entity, (mut energy, _)) in energy_query.iter_entities_mut(world) {
+                        if entity == finished {
+                            energy.value -= cost;
                         }
-                        priority_tuple.sort_by_key(|k| k.0);
-                        pending_moves.list = priority_tuple.iter().map(|v| v.1).collect();
                     }
+                }
 
-                    let next_turn = pending_moves
-                        .list
-                        .pop()
-                        .expect("No entites could take a turn");
-                    command_buffer.add_component(
-                        next_turn,
-                        ActiveTurn {
-                            state: TurnState::PENDING,
-                        },
-                    );
+                let priorities = priority_query
+                    .iter_entities(world)
+                    .map(|(entity, priority)| (entity, priority.value))
+                    .collect::<HashMap<_, _>>();
+                let mut actors = energy_query
+                    .iter_entities_mut(world)
+                    .map(|(entity, (energy, speed))| Actor {
+                        id: entity,
+                        energy: energy.value,
+                        speed: speed.value,
+                        priority: priorities.get(&entity).cloned().unwrap_or(0),
+                    })
+                    .collect::<Vec<_>>();
+                let next_turn = match next_actor(&mut actors) {
+                    Some(entity) => entity,
+                    None => return,
+                };
+
+                let energies = actors
+                    .iter()
+                    .map(|actor| (actor.id, actor.energy))
+                    .collect::<HashMap<_, _>>();
+                for (entity, (mut energy, _)) in energy_query.iter_entities_mut(world) {
+                    energy.value = energies[&entity];
                 }
+                command_buffer.add_component(next_turn, ActiveTurn::pending());
             },
         )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{next_actor, Actor, TURN_ENERGY};
+
+    fn actor(id: u32, energy: i32, speed: i32, priority: u8) -> Actor<u32> {
+        Actor {
+            id,
+            energy,
+            speed,
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_faster_actor_goes_first() {
+        let mut actors = vec![actor(1, 0, 10, 0), actor(2, 0, 20, 0)];
+        assert_eq!(next_actor(&mut actors), Some(2));
+        assert_eq!(actors[0].energy, TURN_ENERGY / 2);
+        assert_eq!(actors[1].energy, TURN_ENERGY);
+    }
+
+    #[test]
+    fn test_ties_break_by_priority() {
+        let mut actors = vec![actor(1, 0, 10, 2), actor(2, 0, 10, 1)];
+        assert_eq!(next_actor(&mut actors), Some(1));
+    }
+
+    #[test]
+    fn test_nobody_to_schedule() {
+        assert_eq!(next_actor::<u32>(&mut []), None);
+        assert_eq!(next_actor(&mut [actor(1, 0, 0, 0)]), None);
+    }
+}
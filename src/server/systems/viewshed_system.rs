@@ -0,0 +1,37 @@
+use crate::component::{Player, Position, Viewshed};
+use crate::map::Map;
+use crate::server::fov::{self, FieldOfView};
+use legion::prelude::*;
+
+/// Works out again what every entity that moved can see. The player's view is also
+/// marked on the map, which is what the client draws the fog of war from.
+pub fn viewshed_system() -> Box<dyn Schedulable> {
+    let mut fov = FieldOfView::default();
+    SystemBuilder::new("viewshed_system")
+        .write_resource::<Map>()
+        .with_query(<(Read<Position>, Write<Viewshed>)>::query())
+        .with_query(<Read<Position>>::query().filter(tag::<Player>()))
+        .build(move |_, world, map, (viewshed_query, player_query)| {
+            let map: &mut Map = map;
+            let player = player_query
+                .iter_entities(world)
+                .next()
+                .map(|(entity, _)| entity);
+            for (entity, (position, mut viewshed)) in viewshed_query.iter_entities_mut(world) {
+                if !viewshed.dirty {
+                    continue;
+                }
+                viewshed.dirty = false;
+                let visible = fov.visible((*position).into(), viewshed.range, map);
+                viewshed.visible_tiles.clear();
+                viewshed.visible_tiles.extend_from_slice(visible);
+                // Keeps saves and replays the same from run to run.
+                viewshed
+                    .visible_tiles
+                    .sort_by_key(|point| (point.y, point.x));
+                if Some(entity) == player {
+                    fov::update_visibility(map, &viewshed.visible_tiles);
+                }
+            }
+        })
+}
@@ -0,0 +1,130 @@
+use crate::color::Color;
+use crate::frontend::glyph::Glyph;
+use crate::map::TileType;
+use quicksilver::load_file;
+use serde::Deserialize;
+use serde_json::from_str;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Clone)]
+struct Data {
+    tiles: Vec<TileData>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TileData {
+    tile: TileType,
+    glyph: TileGlyphData,
+    blocks_movement: bool,
+    blocks_sight: bool,
+    description: String,
+    toggle: Option<TileType>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TileGlyphData {
+    ch: char,
+    foreground: Option<String>,
+    background: Option<String>,
+}
+
+/// How a kind of tile looks and behaves.
+#[derive(Clone, Debug)]
+pub struct TileDefinition {
+    pub glyph: Glyph,
+    pub blocks_movement: bool,
+    pub blocks_sight: bool,
+    pub description: String,
+    /// What the tile turns into when someone interacts with it, like a door opening.
+    pub toggle: Option<TileType>,
+}
+
+/// Every kind of tile, as described in `data/tiles.json`.
+#[derive(Clone, Debug)]
+pub struct TileDefinitions {
+    definitions: HashMap<TileType, TileDefinition>,
+}
+
+impl TileDefinitions {
+    pub async fn load() -> Self {
+        let file_contents = load_file("data/tiles.json")
+            .await
+            .expect("Couldn't find tile definition file");
+        let raw_string = std::str::from_utf8(&file_contents)
+            .expect("Couldn't get raw string from tile definition file");
+        Self::from_json(raw_string)
+    }
+
+    pub fn load_from_path(path: impl AsRef<Path>) -> crate::error::Result<Self> {
+        let raw_string = std::fs::read_to_string(path)?;
+        Ok(Self::from_json(&raw_string))
+    }
+
+    pub fn from_json(raw_string: &str) -> Self {
+        let data: Data = from_str(raw_string).expect("Invalid tile definition file");
+        let color = |color: &Option<String>| color.as_ref().map(|color| Color::from_hex(color));
+        let definitions = data
+            .tiles
+            .into_iter()
+            .map(|tile| {
+                let glyph = Glyph::from(
+                    tile.glyph.ch,
+                    color(&tile.glyph.foreground),
+                    color(&tile.glyph.background),
+                );
+                let definition = TileDefinition {
+                    glyph,
+                    blocks_movement: tile.blocks_movement,
+                    blocks_sight: tile.blocks_sight,
+                    description: tile.description,
+                    toggle: tile.toggle,
+                };
+                (tile.tile, definition)
+            })
+            .collect();
+        TileDefinitions { definitions }
+    }
+
+    pub fn get(&self, tile: TileType) -> &TileDefinition {
+        self.definitions
+            .get(&tile)
+            .unwrap_or_else(|| panic!("No definition for {:?} tiles", tile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TileDefinitions;
+    use crate::map::TileType;
+
+    #[test]
+    fn test_load_tile_definitions() {
+        let tiles = TileDefinitions::load_from_path("static/data/tiles.json").unwrap();
+        let all = [
+            TileType::Wall,
+            TileType::Floor,
+            TileType::Digging,
+            TileType::Entrance,
+            TileType::DownStairs,
+            TileType::UpStairs,
+            TileType::DoorClosed,
+            TileType::DoorOpen,
+            TileType::Counter,
+            TileType::Window,
+        ];
+        for tile in all.iter() {
+            // Toggling twice always gets back to where it started.
+            if let Some(toggle) = tiles.get(*tile).toggle {
+                assert_eq!(tiles.get(toggle).toggle, Some(*tile));
+            }
+        }
+
+        let closed = tiles.get(TileType::DoorClosed);
+        assert!(closed.blocks_movement && closed.blocks_sight);
+        assert_eq!(closed.toggle, Some(TileType::DoorOpen));
+        let open = tiles.get(TileType::DoorOpen);
+        assert!(!open.blocks_movement && !open.blocks_sight);
+        assert_eq!(open.glyph.ch, '\'');
+    }
+}